@@ -0,0 +1,93 @@
+// The crate is a binary, so the conversion modules are compiled into the
+// benchmark directly, without their tests and most of their callers.
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/convert_file.rs"]
+mod convert_file;
+#[path = "../src/convertions.rs"]
+mod convertions;
+#[path = "../src/error.rs"]
+mod error;
+
+use convert_file::Converter;
+use convertions::{default_conversion_table, Convertion, CONVERTIONS};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+// A source file of about 256 KiB: CRLF lines of code, with CP850 accents in
+// the strings and comments and a few of the chr() calls the table rewrites.
+fn source() -> Vec<u8> {
+    let lines: [&[u8]; 8] = [
+        b"FUNCTION ImprimeRelatorio( cArquivo, nPagina )\r\n",
+        b"   LOCAL cTitulo := \"Relat\xa2rio de Situa\x87\xc6o\"\r\n",
+        b"   // Cabe\x87alho com a descri\x87\xc6o do per\xa1odo\r\n",
+        b"   IF nPagina > 1 .AND. !Empty( cArquivo )\r\n",
+        b"      ? cTitulo + chr(251) + \" p\xa0gina \" + Str( nPagina )\r\n",
+        b"      @ 10, 2 SAY \"Observa\x87\x94es:\" GET cObs PICTURE \"@!\"\r\n",
+        b"   ENDIF\r\n",
+        b"RETURN NIL\r\n\r\n",
+    ];
+
+    lines
+        .iter()
+        .copied()
+        .flatten()
+        .copied()
+        .cycle()
+        .take(256 << 10)
+        .collect()
+}
+
+// The conversion before the single pass: one scan of the buffer per entry of
+// the table, splicing each replacement into place.
+fn splice_per_entry(buffer: &mut Vec<u8>) {
+    for (from, to) in CONVERTIONS {
+        let mut i = 0;
+
+        while i < buffer.len() {
+            if buffer[i..].starts_with(from) {
+                buffer.splice(i..i + from.len(), to.iter().cloned());
+                i += to.len();
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+fn convert(criterion: &mut Criterion) {
+    let source = source();
+    // An entry that deletes what it matches forces one pass per entry.
+    let mut sequential_table: Vec<Convertion> = vec![(b"\x00".to_vec(), Vec::new())];
+    sequential_table.extend(default_conversion_table());
+
+    let single_pass = Converter::build(default_conversion_table());
+    let sequential = Converter::build(sequential_table);
+
+    let mut spliced = source.clone();
+    splice_per_entry(&mut spliced);
+    assert_eq!(single_pass.convert(&source).0, spliced);
+    assert_eq!(sequential.convert(&source).0, spliced);
+
+    let mut group = criterion.benchmark_group("convert");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.sample_size(10);
+
+    group.bench_function("single pass", |bencher| {
+        bencher.iter(|| single_pass.convert(&source))
+    });
+    group.bench_function("sequential passes", |bencher| {
+        bencher.iter(|| sequential.convert(&source))
+    });
+    group.bench_function("splice per entry", |bencher| {
+        bencher.iter_batched(
+            || source.clone(),
+            |mut buffer| splice_per_entry(&mut buffer),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, convert);
+criterion_main!(benches);
@@ -0,0 +1,433 @@
+pub enum Arity {
+    Flag,
+    Required(&'static str),
+    Optional(&'static str),
+    Trailing(&'static str),
+}
+
+pub struct OptionSpec {
+    pub short: Option<&'static str>,
+    pub long: &'static str,
+    pub aliases: &'static [&'static str],
+    pub arity: Arity,
+    pub help: &'static str,
+}
+
+pub struct Argument {
+    pub long: &'static str,
+    pub value: Option<String>,
+}
+
+pub const OPTIONS: [OptionSpec; 35] = [
+    OptionSpec {
+        short: Some("-s"),
+        long: "--source",
+        aliases: &[],
+        arity: Arity::Required("source"),
+        help: "Source path (repeat the option or separate with ; for several sources)",
+    },
+    OptionSpec {
+        short: Some("-d"),
+        long: "--destination",
+        aliases: &[],
+        arity: Arity::Required("destination"),
+        help: "Destination path (repeat the option or separate with ; for several destinations)",
+    },
+    OptionSpec {
+        short: Some("-f"),
+        long: "--filter",
+        aliases: &[],
+        arity: Arity::Required("pattern"),
+        help: "Export only the files matching the pattern (repeatable)\n\
+               patterns are globs (*, ?, [abc], {prg,ch}, **/) matched against the file name,\n\
+               or against the path relative to the source when they contain a /;\n\
+               prefix a pattern with re: to use a regular expression instead",
+    },
+    OptionSpec {
+        short: Some("-e"),
+        long: "--exclude",
+        aliases: &[],
+        arity: Arity::Required("pattern"),
+        help: "Do not export the files matching the pattern, even if a filter matches them (repeatable)\n\
+               a pattern ending with / excludes whole directories, !pattern exports a file again;\n\
+               the last matching pattern wins; exclude in exportbranch.exb changes them\n\
+               per directory",
+    },
+    OptionSpec {
+        short: Some("-c"),
+        long: "--only-copy",
+        aliases: &[],
+        arity: Arity::Required("pattern"),
+        help: "Copy the files matching the pattern without converting them (repeatable)",
+    },
+    OptionSpec {
+        short: Some("-x"),
+        long: "--disregard",
+        aliases: &[],
+        arity: Arity::Required("directory"),
+        help: "Directory not exported, relative to the source parent (repeatable)\n\
+               disregard in exportbranch.exb adds or, with -=, restores them per directory",
+    },
+    OptionSpec {
+        short: Some("-j"),
+        long: "--jobs",
+        aliases: &[],
+        arity: Arity::Required("jobs"),
+        help: "Number of worker threads (defaults to the available CPUs)",
+    },
+    OptionSpec {
+        short: None,
+        long: "--config",
+        aliases: &[],
+        arity: Arity::Required("file"),
+        help: "Read settings from file (defaults to ./exportbranch.toml when present)\n\
+               command line options override the file",
+    },
+    OptionSpec {
+        short: None,
+        long: "--profile",
+        aliases: &[],
+        arity: Arity::Required("name"),
+        help: "Apply the [profile.<name>] table of the configuration file on top of its\n\
+               top-level settings (a profile may name another one with inherits = \"name\")",
+    },
+    OptionSpec {
+        short: None,
+        long: "--list-profiles",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "List the profiles of the configuration file",
+    },
+    OptionSpec {
+        short: None,
+        long: "--conversion-table",
+        aliases: &[],
+        arity: Arity::Required("file"),
+        help: "Read the byte conversions from file instead of the built-in table",
+    },
+    OptionSpec {
+        short: None,
+        long: "--dump-conversion-table",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Print the conversion table in the --conversion-table format",
+    },
+    OptionSpec {
+        short: None,
+        long: "--md5",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Compare files by content digest instead of modification time",
+    },
+    OptionSpec {
+        short: None,
+        long: "--md5-output",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Same as --md5, also re-exporting files whose destination content changed",
+    },
+    OptionSpec {
+        short: None,
+        long: "--reload",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Export every file, even the up to date ones",
+    },
+    OptionSpec {
+        short: None,
+        long: "--lower",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Lowercase the exported file and directory names",
+    },
+    OptionSpec {
+        short: None,
+        long: "--ignore-case",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Match the filter and only copy patterns ignoring case",
+    },
+    OptionSpec {
+        short: None,
+        long: "--ignore-files",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Do not export what .gitignore, .git/info/exclude or .exbignore files ignore",
+    },
+    OptionSpec {
+        short: None,
+        long: "--tracked",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Export only the files tracked by the git repository of the source",
+    },
+    OptionSpec {
+        short: None,
+        long: "--since",
+        aliases: &[],
+        arity: Arity::Required("revision"),
+        help: "Export only the tracked files whose working tree content differs from the revision\n\
+               (a branch, tag or commit, optionally with ~n or ^n), whatever their modification time",
+    },
+    OptionSpec {
+        short: None,
+        long: "--invalid-exb",
+        aliases: &[],
+        arity: Arity::Required("policy"),
+        help: "What to do with unreadable .exb files and invalid entries in them: error (the default)\n\
+               stops with their path, line and pattern, warn reports them and skips the entry",
+    },
+    OptionSpec {
+        short: None,
+        long: "--mirror",
+        aliases: &["--prune"],
+        arity: Arity::Flag,
+        help: "Remove exported files whose source was deleted or no longer matches the filters",
+    },
+    OptionSpec {
+        short: None,
+        long: "--watch",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "After exporting, keep re-exporting files as they change",
+    },
+    OptionSpec {
+        short: None,
+        long: "--dry-run",
+        aliases: &[],
+        arity: Arity::Optional("json"),
+        help:
+            "List the files that would be copied, converted or skipped without writing anything\n\
+               --dry-run=json is the same as --dry-run --output json",
+    },
+    OptionSpec {
+        short: None,
+        long: "--output",
+        aliases: &[],
+        arity: Arity::Required("format"),
+        help: "Output format: text (the default) or json, one JSON event per line for the run start,\n\
+               each exported, failed or removed file and the run summary",
+    },
+    OptionSpec {
+        short: Some("-q"),
+        long: "--quiet",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Print only the errors and the summary",
+    },
+    OptionSpec {
+        short: Some("-v"),
+        long: "--verbose",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Print the reason each file was exported",
+    },
+    OptionSpec {
+        short: Some("-vv"),
+        long: "--very-verbose",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Also print the skipped and up to date files with the reason they were left out",
+    },
+    OptionSpec {
+        short: None,
+        long: "--log-file",
+        aliases: &[],
+        arity: Arity::Required("file"),
+        help: "Append the per-file lines and the summary to the file instead of printing them",
+    },
+    OptionSpec {
+        short: None,
+        long: "--show",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Print the configuration and where each value came from before exporting",
+    },
+    OptionSpec {
+        short: None,
+        long: "--explain",
+        aliases: &[],
+        arity: Arity::Required("file"),
+        help: "Print how the file would be exported and which .exb files and patterns decided it,\n\
+               without exporting anything",
+    },
+    OptionSpec {
+        short: None,
+        long: "--diff",
+        aliases: &[],
+        arity: Arity::Required("format"),
+        help: "Also print the difference between the file and its converted output with --explain:\n\
+               unified (the default of the explain command) or side-by-side",
+    },
+    OptionSpec {
+        short: None,
+        long: "--report-conversions",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Print after each converted file which conversion table entries were applied,\n\
+               how many times and at which line:column",
+    },
+    OptionSpec {
+        short: Some("-h"),
+        long: "--help",
+        aliases: &[],
+        arity: Arity::Trailing("option"),
+        help: "Print this help, or only the help of the given option",
+    },
+    OptionSpec {
+        short: Some("-V"),
+        long: "--version",
+        aliases: &[],
+        arity: Arity::Flag,
+        help: "Print the version",
+    },
+];
+
+impl OptionSpec {
+    pub fn names(&self) -> String {
+        let mut names: Vec<&str> = self.short.iter().copied().collect();
+        names.push(self.long);
+        names.extend(self.aliases);
+
+        let names = names.join(", ");
+
+        match self.arity {
+            Arity::Flag => names,
+            Arity::Required(value) => format!("{} <{}>", names, value),
+            Arity::Optional(value) => format!("{}[={}]", names, value),
+            Arity::Trailing(value) => format!("{} [{}]", names, value),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.short == Some(name) || self.long == name || self.aliases.contains(&name)
+    }
+}
+
+pub fn find_option(name: &str) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|option| option.matches(name))
+}
+
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Vec<Argument>, String> {
+    let mut arguments = Vec::new();
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            return Err(format!("Unexpected argument \"{}\"", arg));
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        let option = match find_option(name) {
+            Some(option) => option,
+            None => return Err(format!("Unknown option \"{}\"", name)),
+        };
+
+        let value = match option.arity {
+            Arity::Flag => {
+                if inline_value.is_some() {
+                    return Err(format!("Option \"{}\" does not take a value", name));
+                }
+                None
+            }
+            Arity::Required(value_name) => match inline_value.or_else(|| {
+                args.next_if(|next| find_option(next.split('=').next().unwrap_or("")).is_none())
+            }) {
+                Some(value) if !value.is_empty() => Some(value),
+                _ => {
+                    return Err(format!(
+                        "Option \"{}\" requires a value <{}>",
+                        name, value_name
+                    ))
+                }
+            },
+            Arity::Optional(_) => inline_value,
+            Arity::Trailing(_) => inline_value.or_else(|| args.next()),
+        };
+
+        arguments.push(Argument {
+            long: option.long,
+            value,
+        });
+    }
+
+    Ok(arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Vec<(&'static str, Option<String>)>, String> {
+        Ok(parse(args.iter().map(|arg| arg.to_string()))?
+            .into_iter()
+            .map(|argument| (argument.long, argument.value))
+            .collect())
+    }
+
+    fn value(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn values_follow_the_option_or_an_equals_sign() {
+        assert_eq!(
+            parse_args(&[
+                "--source=a",
+                "-d",
+                "b",
+                "-e",
+                "-old.prg",
+                "--prune",
+                "--dry-run=json",
+                "--md5",
+                "--help",
+                "--jobs",
+            ])
+            .ok(),
+            Some(vec![
+                ("--source", value("a")),
+                ("--destination", value("b")),
+                ("--exclude", value("-old.prg")),
+                ("--mirror", None),
+                ("--dry-run", value("json")),
+                ("--md5", None),
+                ("--help", value("--jobs")),
+            ])
+        );
+    }
+
+    #[test]
+    fn repeated_options_are_kept_in_order() {
+        assert_eq!(
+            parse_args(&["-s", "a", "--source=b", "-f", "*.prg", "-s", "c"]).ok(),
+            Some(vec![
+                ("--source", value("a")),
+                ("--source", value("b")),
+                ("--filter", value("*.prg")),
+                ("--source", value("c")),
+            ])
+        );
+    }
+
+    #[test]
+    fn errors_name_the_option() {
+        for (args, error) in [
+            (&["--colour"][..], "Unknown option \"--colour\""),
+            (&["-s"], "Option \"-s\" requires a value <source>"),
+            (&["-s", "--md5"], "Option \"-s\" requires a value <source>"),
+            (
+                &["--source="],
+                "Option \"--source\" requires a value <source>",
+            ),
+            (&["--md5=yes"], "Option \"--md5\" does not take a value"),
+            (&["-s", "a", "b"], "Unexpected argument \"b\""),
+        ] {
+            assert_eq!(parse_args(args).err().as_deref(), Some(error));
+        }
+    }
+}
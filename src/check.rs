@@ -0,0 +1,116 @@
+use crate::configuration::Configuration;
+use crate::error::{Error, Result};
+use crate::export_branch_files::{read_configuration_files, report_problems, Filters};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Reads every configuration file under the sources, whether or not the export
+// would reach it, and reports all the problems found instead of the first one.
+pub fn check(configuration: &Configuration) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut checked = 0;
+
+    for source in configuration.source() {
+        let source = Path::new(source)
+            .canonicalize()
+            .map_err(|err| Error::io(Path::new(source), err))?;
+        let filters = Filters::build(configuration, &source, &mut problems)?;
+
+        check_directory(
+            &source,
+            &filters,
+            configuration.ignore_case(),
+            &mut checked,
+            &mut problems,
+        );
+    }
+
+    println!(
+        "{} configuration files checked, {} problems found",
+        checked,
+        problems.len()
+    );
+
+    report_problems(problems, configuration.invalid_exb())
+}
+
+fn check_directory(
+    directory: &Path,
+    filters: &Filters,
+    ignore_case: bool,
+    checked: &mut usize,
+    problems: &mut Vec<Error>,
+) {
+    let filters = read_configuration_files(directory, filters, ignore_case, problems);
+
+    *checked += filters
+        .configuration_files
+        .iter()
+        .filter(|file| file.parent() == Some(directory))
+        .count();
+
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) => {
+            problems.push(Error::io(directory, err));
+            return;
+        }
+    };
+
+    let mut directories: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().is_some_and(|name| name != ".git"))
+        .collect();
+    directories.sort();
+
+    for subdirectory in directories {
+        check_directory(&subdirectory, &filters, ignore_case, checked, problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{configuration, malformed_source, TempDirectory};
+
+    fn check_source(source: &Path, policy: &str) -> Result<()> {
+        check(&configuration(&[
+            "check",
+            "-s",
+            &source.to_string_lossy(),
+            "--invalid-exb",
+            policy,
+        ]))
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let directory = TempDirectory::new();
+        let source = malformed_source(&directory);
+        let unreadable = source.join("sub").join("exportbranch.exb");
+
+        match check_source(&source, "error") {
+            Err(Error::Multiple(problems)) => {
+                let problems: Vec<String> = problems.iter().map(Error::to_string).collect();
+
+                assert_eq!(problems.len(), 5, "{:?}", problems);
+                assert!(problems[0].contains("exportbranch.exb:1"));
+                assert!(problems[1].contains("exportbranch.exb:2"));
+                assert!(problems[2].contains("exportbranch.exb:3"));
+                assert!(problems[3].contains("extecoesapenascopiar.exb:1"));
+                assert!(problems[4].starts_with(&*unreadable.to_string_lossy()));
+            }
+            _ => panic!("expected several problems"),
+        }
+    }
+
+    #[test]
+    fn warnings_do_not_fail_the_check() {
+        let directory = TempDirectory::new();
+        let source = malformed_source(&directory);
+
+        assert!(check_source(&source, "warn").is_ok());
+    }
+}
@@ -1,8 +1,10 @@
-use crate::help::help;
-use std::{
-    path::Path,
-    result::Result::{Err, Ok},
-};
+use crate::arguments::parse;
+use crate::convertions::{default_conversion_table, read_conversion_table, Convertion};
+use crate::error::{Error, Result};
+use crate::file_pattern::{build_exclusions, build_patterns};
+use crate::help::{help, help_for, version};
+use crate::settings::{Settings, SettingsFile, CONFIGURATION_FILE};
+use std::path::{Path, PathBuf};
 
 const DEFAULT_ONLY_COPY_FILES: [&str; 5] = ["*.a", "*.so", "*.h", "*.0", "*.18"];
 
@@ -19,128 +21,583 @@ const DISREGARDED_DIRECTORIES: [&str; 5] = [
     "programas_externos/hbfunctions",
 ];
 
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    // Errors and the summary only.
+    Quiet,
+    // One line per exported file.
+    #[default]
+    Normal,
+    // The reason each file was exported.
+    Verbose,
+    // Skipped and up-to-date files too.
+    VeryVerbose,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ExbPolicy {
+    #[default]
+    Error,
+    Warn,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Command {
+    #[default]
+    Export,
+    Check,
+    Verify,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DiffFormat {
+    Unified,
+    SideBySide,
+}
+
+#[derive(Default)]
 pub struct Configuration {
+    command: Command,
     source: Vec<String>,
     destination: Vec<String>,
     only_copy_files: Vec<String>,
     file_filters: Vec<String>,
+    exclusions: Vec<String>,
     show: bool,
     md5: bool,
+    md5_output: bool,
     reload: bool,
     lower: bool,
-    disregarded_directories: Vec<String>,
+    ignore_case: bool,
+    ignore_files: bool,
+    tracked: bool,
+    since: Option<String>,
+    invalid_exb: ExbPolicy,
+    jobs: Option<usize>,
+    disregarded_directories: Vec<PathBuf>,
+    convertions: Vec<Convertion>,
+    conversion_table: Option<String>,
+    message: Option<String>,
+    dump_conversion_table: bool,
+    list_profiles: bool,
+    explain: Option<String>,
+    diff: Option<DiffFormat>,
+    report_conversions: bool,
+    profiles: Vec<(String, Option<String>)>,
+    dry_run: bool,
+    mirror: bool,
+    watch: bool,
+    output: OutputFormat,
+    verbosity: Verbosity,
+    log_file: Option<PathBuf>,
+    origins: Vec<(&'static str, Origin)>,
+}
+
+struct Merger<'a> {
+    layers: Vec<(Origin, Settings)>,
+    origins: &'a mut Vec<(&'static str, Origin)>,
+}
+
+impl Merger<'_> {
+    fn merge<T>(
+        &mut self,
+        key: &'static str,
+        command_line: Option<T>,
+        file: impl Fn(&Settings) -> Option<T>,
+        default: T,
+    ) -> T {
+        if let Some(value) = command_line {
+            self.origins.push((key, Origin::CommandLine));
+            return value;
+        }
+
+        for (origin, settings) in self.layers.iter().rev() {
+            if let Some(value) = file(settings) {
+                self.origins.push((key, origin.clone()));
+                return value;
+            }
+        }
+
+        self.origins.push((key, Origin::Default));
+        default
+    }
+}
+
+#[derive(Clone)]
+pub enum Origin {
+    Default,
+    CommandLine,
+    File(PathBuf),
+    Profile(PathBuf, String),
 }
 
 impl Configuration {
-    pub fn build(args: &mut impl Iterator<Item = String>) -> Result<Configuration, String> {
-        let mut destination = Vec::new();
-        let mut source = Vec::new();
-        let mut md5 = false;
-        let mut reload = false;
-        let mut lower = false;
-        let mut show = false;
-        let mut only_copy_files = vec![];
-        let mut file_filters = vec![];
-        let mut previous_arg = String::new();
+    pub fn build(args: &mut impl Iterator<Item = String>) -> Result<Configuration> {
+        let mut command_line = Settings::default();
+        let mut configuration_file = None;
+        let mut profile = None;
+        let mut dump_conversion_table = false;
+        let mut list_profiles = false;
+        let mut explain = None;
+        let mut diff = None;
+        let mut report_conversions = false;
+        let mut log_file = None;
 
         args.next();
 
-        for arg in args {
-            if arg == "-d" || arg == "-s" || arg == "-c" || arg == "-f" {
-                previous_arg = arg;
-                continue;
-            } else if previous_arg == "-d" {
-                for path in arg.split(";") {
-                    destination.push(path.to_string());
+        let mut args = args.peekable();
+        let command = match args.peek().map(String::as_str) {
+            Some("check") => {
+                args.next();
+                Command::Check
+            }
+            // An --explain that also shows the conversion diff.
+            Some("explain") => {
+                args.next();
+                explain = Some(
+                    args.next_if(|arg| !arg.starts_with('-'))
+                        .ok_or_else(|| usage_error("The explain command requires a file"))?,
+                );
+                diff = Some(DiffFormat::Unified);
+                Command::Export
+            }
+            Some("verify") => {
+                args.next();
+                Command::Verify
+            }
+            _ => Command::Export,
+        };
+
+        for argument in parse(args).map_err(|err| usage_error(&err))? {
+            let value = argument.value.unwrap_or_default();
+
+            match argument.long {
+                "--source" => command_line
+                    .source
+                    .get_or_insert_with(Vec::new)
+                    .extend(split_arg(&value)),
+                "--destination" => command_line
+                    .destination
+                    .get_or_insert_with(Vec::new)
+                    .extend(split_arg(&value)),
+                "--filter" => command_line
+                    .file_filters
+                    .get_or_insert_with(Vec::new)
+                    .extend(split_arg(&value)),
+                "--exclude" => command_line
+                    .exclusions
+                    .get_or_insert_with(Vec::new)
+                    .extend(split_arg(&value)),
+                "--only-copy" => command_line
+                    .only_copy_files
+                    .get_or_insert_with(Vec::new)
+                    .extend(split_arg(&value)),
+                "--disregard" => command_line
+                    .disregarded_directories
+                    .get_or_insert_with(Vec::new)
+                    .extend(split_arg(&value)),
+                "--jobs" => match value.parse::<usize>() {
+                    Ok(threads) if threads > 0 => command_line.jobs = Some(threads),
+                    _ => {
+                        return Err(usage_error(&format!(
+                            "Invalid value \"{}\" for \"--jobs\": expected a positive integer",
+                            value
+                        )))
+                    }
+                },
+                "--conversion-table" => command_line.conversion_table = Some(value),
+                "--config" => configuration_file = Some(value),
+                "--profile" => profile = Some(value),
+                "--dump-conversion-table" => dump_conversion_table = true,
+                "--list-profiles" => list_profiles = true,
+                "--md5" => command_line.md5 = Some(true),
+                "--md5-output" => command_line.md5_output = Some(true),
+                "--reload" => command_line.reload = Some(true),
+                "--lower" => command_line.lower = Some(true),
+                "--ignore-case" => command_line.ignore_case = Some(true),
+                "--ignore-files" => command_line.ignore_files = Some(true),
+                "--tracked" => command_line.tracked = Some(true),
+                "--since" => command_line.since = Some(value),
+                "--invalid-exb" => command_line.invalid_exb = Some(value),
+                "--dry-run" => {
+                    command_line.dry_run = Some(true);
+                    match value.as_str() {
+                        "" => {}
+                        "json" => command_line.output = Some(value),
+                        _ => {
+                            return Err(usage_error(&format!(
+                                "Invalid value \"{}\" for \"--dry-run\": expected json",
+                                value
+                            )))
+                        }
+                    }
+                }
+                "--output" => {
+                    output_format(&value, "--output")?;
+                    command_line.output = Some(value);
                 }
-            } else if previous_arg == "-s" {
-                for path in arg.split(";") {
-                    source.push(path.to_string());
+                "--diff" => {
+                    diff = match value.as_str() {
+                        "unified" => Some(DiffFormat::Unified),
+                        "side-by-side" => Some(DiffFormat::SideBySide),
+                        _ => return Err(usage_error(&format!(
+                            "Invalid value \"{}\" for \"--diff\": expected unified or side-by-side",
+                            value
+                        ))),
+                    }
                 }
-            } else if previous_arg == "-c" {
-                only_copy_files = Vec::new();
-                for file in arg.split(";") {
-                    only_copy_files.push(file.to_string());
+                "--report-conversions" => report_conversions = true,
+                "--quiet" => command_line.verbosity = Some("quiet".to_string()),
+                "--verbose" => command_line.verbosity = Some("verbose".to_string()),
+                "--very-verbose" => command_line.verbosity = Some("very-verbose".to_string()),
+                "--log-file" => log_file = Some(PathBuf::from(value)),
+                "--mirror" => command_line.mirror = Some(true),
+                "--watch" => command_line.watch = Some(true),
+                "--show" => command_line.show = Some(true),
+                "--explain" => explain = Some(value),
+                "--help" => {
+                    return Ok(Configuration {
+                        message: Some(match value.as_str() {
+                            "" => help(),
+                            option => help_for(option).map_err(|err| usage_error(&err))?,
+                        }),
+                        ..Configuration::default()
+                    })
                 }
-            } else if previous_arg == "-f" {
-                file_filters = Vec::new();
-                for file in arg.split(";") {
-                    file_filters.push(file.to_string());
+                "--version" => {
+                    return Ok(Configuration {
+                        message: Some(version()),
+                        ..Configuration::default()
+                    })
                 }
-            } else if arg == "--md5" {
-                md5 = true;
-            } else if arg == "--reload" {
-                reload = true;
-            } else if arg == "--lower" {
-                lower = true;
-            } else if arg == "--show" {
-                show = true;
-            } else {
-                return Err(help());
+                _ => unreachable!(),
             }
-            previous_arg = String::new();
         }
 
-        destination = destination.into_iter().filter(|x| x.len() > 0).collect::<Vec<String>>();
-        source = source.into_iter().filter(|x| x.len() > 0).collect::<Vec<String>>();
+        let settings_file =
+            SettingsFile::discover(configuration_file.as_deref()).map_err(Error::Config)?;
+        let mut layers: Vec<(Origin, Settings)> = Vec::new();
 
-        if destination.len() < 1 || source.len() < 1 {
-            return Err(help());
-        }
+        if let Some(settings_file) = &settings_file {
+            let path = settings_file.path().to_path_buf();
 
-        let mut disregarded_directories = Vec::new();
+            layers.push((
+                Origin::File(path.clone()),
+                settings_file.settings().map_err(Error::Config)?,
+            ));
 
-        for source_directory in &source {
-            for disregarded_directory in DISREGARDED_DIRECTORIES {
-                let source_path = Path::new(&source_directory).canonicalize().unwrap_or_else(|err| {
-                    eprintln!("{}", err);
-                    std::process::exit(1);
-                });
-                disregarded_directories
-                    .push(Path::new(&source_path).parent().unwrap().join(disregarded_directory).to_str().unwrap().to_string());
+            if let Some(profile) = &profile {
+                for (name, settings) in settings_file.profile(profile).map_err(Error::Config)? {
+                    layers.push((Origin::Profile(path.clone(), name), settings));
+                }
             }
+        } else if let Some(profile) = &profile {
+            return Err(Error::Config(format!(
+                "Profile \"{}\" requires a configuration file ({} or --config <file>)",
+                profile, CONFIGURATION_FILE
+            )));
         }
 
-        Ok(Configuration {
-            source,
-            destination,
-            only_copy_files: {
-                if only_copy_files.len() < 1 {
-                    DEFAULT_ONLY_COPY_FILES.map(|x| x.to_string()).to_vec()
-                } else {
-                    only_copy_files
-                }
-            },
-            file_filters: {
-                if file_filters.len() < 1 {
-                    DEFAULT_FILTERS.map(|x| x.to_string()).to_vec()
-                } else {
-                    file_filters
+        let mut configuration = Configuration {
+            command,
+            dump_conversion_table,
+            list_profiles,
+            explain,
+            diff,
+            report_conversions,
+            log_file,
+            ..Configuration::default()
+        };
+
+        if list_profiles {
+            configuration.profiles = match &settings_file {
+                Some(settings_file) => settings_file.profiles().map_err(Error::Config)?,
+                None => {
+                    return Err(Error::Config(format!(
+                        "No configuration file found ({} or --config <file>)",
+                        CONFIGURATION_FILE
+                    )))
                 }
-            },
-            md5,
-            reload,
-            lower,
-            disregarded_directories,
-            show,
-        })
+            };
+            return Ok(configuration);
+        }
+
+        let mut merger = Merger {
+            layers,
+            origins: &mut configuration.origins,
+        };
+
+        let conversion_table = merger.merge(
+            "conversion",
+            command_line.conversion_table.map(Some),
+            |x| x.conversion_table.clone().map(Some),
+            None,
+        );
+        configuration.convertions = match &conversion_table {
+            Some(conversion_table) => read_conversion_table(Path::new(conversion_table))?,
+            None => default_conversion_table(),
+        };
+        configuration.conversion_table = conversion_table;
+
+        if dump_conversion_table {
+            return Ok(configuration);
+        }
+
+        configuration.source =
+            merger.merge("source", command_line.source, |x| x.source.clone(), vec![]);
+        configuration.destination = merger.merge(
+            "destination",
+            command_line.destination,
+            |x| x.destination.clone(),
+            vec![],
+        );
+        configuration.only_copy_files = merger.merge(
+            "only_copy_files",
+            command_line.only_copy_files.filter(|x| !x.is_empty()),
+            |x| x.only_copy_files.clone().filter(|x| !x.is_empty()),
+            DEFAULT_ONLY_COPY_FILES.map(|x| x.to_string()).to_vec(),
+        );
+        configuration.file_filters = merger.merge(
+            "file_filters",
+            command_line.file_filters.filter(|x| !x.is_empty()),
+            |x| x.file_filters.clone().filter(|x| !x.is_empty()),
+            DEFAULT_FILTERS.map(|x| x.to_string()).to_vec(),
+        );
+        configuration.exclusions = merger.merge(
+            "exclude",
+            command_line.exclusions,
+            |x| x.exclusions.clone(),
+            vec![],
+        );
+        let extra_disregarded_directories = merger.merge(
+            "disregarded",
+            command_line.disregarded_directories,
+            |x| x.disregarded_directories.clone(),
+            vec![],
+        );
+        configuration.jobs = merger.merge(
+            "jobs",
+            command_line.jobs.map(Some),
+            |x| x.jobs.map(Some),
+            None,
+        );
+        configuration.md5_output = merger.merge(
+            "md5_output",
+            command_line.md5_output,
+            |x| x.md5_output,
+            false,
+        );
+        configuration.md5 =
+            merger.merge("md5", command_line.md5, |x| x.md5, false) || configuration.md5_output;
+        configuration.reload = merger.merge("reload", command_line.reload, |x| x.reload, false);
+        configuration.lower = merger.merge("lower", command_line.lower, |x| x.lower, false);
+        configuration.ignore_case = merger.merge(
+            "ignore_case",
+            command_line.ignore_case,
+            |x| x.ignore_case,
+            false,
+        );
+        configuration.mirror = merger.merge("mirror", command_line.mirror, |x| x.mirror, false);
+        configuration.watch = merger.merge("watch", command_line.watch, |x| x.watch, false);
+        configuration.show = merger.merge("show", command_line.show, |x| x.show, false);
+        configuration.dry_run = merger.merge("dry_run", command_line.dry_run, |x| x.dry_run, false);
+        configuration.output = output_format(
+            &merger.merge(
+                "output",
+                command_line.output,
+                |x| x.output.clone(),
+                "text".to_string(),
+            ),
+            "output",
+        )?;
+        configuration.verbosity = verbosity(&merger.merge(
+            "verbosity",
+            command_line.verbosity,
+            |x| x.verbosity.clone(),
+            "normal".to_string(),
+        ))?;
+
+        configuration.ignore_files = merger.merge(
+            "ignore_files",
+            command_line.ignore_files,
+            |x| x.ignore_files,
+            false,
+        );
+
+        configuration.tracked = merger.merge("tracked", command_line.tracked, |x| x.tracked, false);
+        configuration.since = merger.merge(
+            "since",
+            command_line.since.map(Some),
+            |x| x.since.clone().map(Some),
+            None,
+        );
+
+        configuration.invalid_exb = match merger
+            .merge(
+                "invalid_exb",
+                command_line.invalid_exb,
+                |x| x.invalid_exb.clone(),
+                "error".to_string(),
+            )
+            .as_str()
+        {
+            "error" => ExbPolicy::Error,
+            "warn" => ExbPolicy::Warn,
+            value => {
+                return Err(usage_error(&format!(
+                    "Invalid value \"{}\" for \"invalid_exb\": expected error or warn",
+                    value
+                )))
+            }
+        };
+
+        for patterns in [&configuration.file_filters, &configuration.only_copy_files] {
+            build_patterns(patterns, Path::new(""), configuration.ignore_case, "")?;
+        }
+        build_exclusions(
+            &configuration.exclusions,
+            Path::new(""),
+            configuration.ignore_case,
+            "",
+        )?;
+
+        configuration.destination.retain(|x| !x.is_empty());
+        configuration.source.retain(|x| !x.is_empty());
+
+        if configuration.source.is_empty() {
+            return Err(usage_error(&format!(
+                "Missing source: pass -s/--source or set source in {}",
+                CONFIGURATION_FILE
+            )));
+        }
+
+        if configuration.destination.is_empty()
+            && matches!(configuration.command, Command::Export | Command::Verify)
+            && configuration.explain.is_none()
+        {
+            return Err(usage_error(&format!(
+                "Missing destination: pass -d/--destination or set destination in {}",
+                CONFIGURATION_FILE
+            )));
+        }
+
+        for source_directory in &configuration.source {
+            let source_path = Path::new(&source_directory)
+                .canonicalize()
+                .map_err(|err| Error::io(Path::new(&source_directory), err))?;
+            let parent = source_path.parent().unwrap_or(&source_path);
+
+            for disregarded_directory in DISREGARDED_DIRECTORIES
+                .iter()
+                .copied()
+                .chain(extra_disregarded_directories.iter().map(String::as_str))
+                .filter(|x| !x.is_empty())
+            {
+                configuration
+                    .disregarded_directories
+                    .push(parent.join(disregarded_directory));
+            }
+        }
+
+        Ok(configuration)
     }
 
     pub fn print(&self) {
+        if self.output == OutputFormat::Json {
+            return;
+        }
+
         if self.show {
-            println!(
-                "Export Branch\r\nsource.........: {:?}\r\ndestination....: {:?}\r\nonly_copy_files: {:?}\r\nfile_filters...: {:?}\r\nmd5............: {:?}\r\nreload.........: {:?}\r\nlower..........: {:?}\r\ndisregarded....: {:?}\r\n",
-                self.source,
-                self.destination,
-                self.only_copy_files,
-                self.file_filters,
-                self.md5,
-                self.reload,
-                self.lower,
-                self.disregarded_directories,
-            );
+            println!("Export Branch");
+
+            for (key, value) in [
+                ("source", format!("{:?}", self.source)),
+                ("destination", format!("{:?}", self.destination)),
+                ("only_copy_files", format!("{:?}", self.only_copy_files)),
+                ("file_filters", format!("{:?}", self.file_filters)),
+                ("exclude", format!("{:?}", self.exclusions)),
+                ("md5", format!("{:?}", self.md5)),
+                ("md5_output", format!("{:?}", self.md5_output)),
+                ("reload", format!("{:?}", self.reload)),
+                ("lower", format!("{:?}", self.lower)),
+                ("ignore_case", format!("{:?}", self.ignore_case)),
+                ("ignore_files", format!("{:?}", self.ignore_files)),
+                ("tracked", format!("{:?}", self.tracked)),
+                ("since", self.since.as_deref().unwrap_or("none").to_string()),
+                (
+                    "invalid_exb",
+                    match self.invalid_exb {
+                        ExbPolicy::Error => "error",
+                        ExbPolicy::Warn => "warn",
+                    }
+                    .to_string(),
+                ),
+                ("jobs", format!("{:?}", self.jobs())),
+                ("disregarded", format!("{:?}", self.disregarded_directories)),
+                (
+                    "conversion",
+                    self.conversion_table
+                        .as_deref()
+                        .unwrap_or("built-in")
+                        .to_string(),
+                ),
+                ("dry_run", format!("{:?}", self.dry_run)),
+                (
+                    "output",
+                    match self.output {
+                        OutputFormat::Text => "text",
+                        OutputFormat::Json => "json",
+                    }
+                    .to_string(),
+                ),
+                (
+                    "verbosity",
+                    match self.verbosity {
+                        Verbosity::Quiet => "quiet",
+                        Verbosity::Normal => "normal",
+                        Verbosity::Verbose => "verbose",
+                        Verbosity::VeryVerbose => "very-verbose",
+                    }
+                    .to_string(),
+                ),
+                ("mirror", format!("{:?}", self.mirror)),
+                ("watch", format!("{:?}", self.watch)),
+            ] {
+                println!("{:.<15}: {} ({})", key, value, self.origin(key));
+            }
+
+            println!();
+        }
+
+        if self.verbosity > Verbosity::Quiet && !self.dry_run {
+            println!("--------------------------\r\nExporting...\r\n");
+        }
+    }
+
+    pub fn origin(&self, key: &str) -> String {
+        match self
+            .origins
+            .iter()
+            .find(|(origin_key, _)| *origin_key == key)
+        {
+            Some((_, Origin::CommandLine)) => "command line".to_string(),
+            Some((_, Origin::File(path))) => path.to_string_lossy().to_string(),
+            Some((_, Origin::Profile(path, name))) => {
+                format!("{} [profile {}]", path.to_string_lossy(), name)
+            }
+            _ => "default".to_string(),
         }
-        println!("--------------------------\r\nExporting...\r\n");
+    }
+
+    pub fn command(&self) -> Command {
+        self.command
     }
 
     pub fn source(&self) -> &Vec<String> {
@@ -159,10 +616,18 @@ impl Configuration {
         &self.file_filters
     }
 
+    pub fn exclusions(&self) -> &Vec<String> {
+        &self.exclusions
+    }
+
     pub fn md5(&self) -> bool {
         self.md5
     }
 
+    pub fn md5_output(&self) -> bool {
+        self.md5_output
+    }
+
     pub fn reload(&self) -> bool {
         self.reload
     }
@@ -171,7 +636,185 @@ impl Configuration {
         self.lower
     }
 
-    pub fn disregarded_directories(&self) -> &Vec<String> {
+    pub fn ignore_case(&self) -> bool {
+        self.ignore_case
+    }
+
+    pub fn ignore_files(&self) -> bool {
+        self.ignore_files
+    }
+
+    pub fn tracked(&self) -> bool {
+        self.tracked || self.since.is_some()
+    }
+
+    pub fn since(&self) -> Option<&str> {
+        self.since.as_deref()
+    }
+
+    pub fn invalid_exb(&self) -> ExbPolicy {
+        self.invalid_exb
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1)
+        })
+    }
+
+    pub fn disregarded_directories(&self) -> &Vec<PathBuf> {
         &self.disregarded_directories
     }
+
+    pub fn convertions(&self) -> &Vec<Convertion> {
+        &self.convertions
+    }
+
+    pub fn conversion_table(&self) -> Option<&str> {
+        self.conversion_table.as_deref()
+    }
+
+    pub fn message(&self) -> Option<&String> {
+        self.message.as_ref()
+    }
+
+    pub fn dump_conversion_table(&self) -> bool {
+        self.dump_conversion_table
+    }
+
+    pub fn list_profiles(&self) -> bool {
+        self.list_profiles
+    }
+
+    pub fn explain(&self) -> Option<&str> {
+        self.explain.as_deref()
+    }
+
+    pub fn diff(&self) -> Option<DiffFormat> {
+        self.diff
+    }
+
+    pub fn report_conversions(&self) -> bool {
+        self.report_conversions
+    }
+
+    pub fn profiles(&self) -> &Vec<(String, Option<String>)> {
+        &self.profiles
+    }
+
+    // verify walks the source like an export but never writes to the destination.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run || self.command == Command::Verify
+    }
+
+    pub fn mirror(&self) -> bool {
+        self.mirror
+    }
+
+    pub fn watch(&self) -> bool {
+        self.watch
+    }
+
+    pub fn output(&self) -> OutputFormat {
+        self.output
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    pub fn report_skipped(&self) -> bool {
+        self.dry_run
+            || (self.verbosity == Verbosity::VeryVerbose && self.output == OutputFormat::Text)
+    }
+
+    pub fn log_file(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+}
+
+fn usage_error(err: &str) -> Error {
+    Error::Config(format!("{}\nRun \"exportbranch --help\" for usage.", err))
+}
+
+fn split_arg(arg: &str) -> Vec<String> {
+    arg.split(';')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+fn output_format(value: &str, key: &str) -> Result<OutputFormat> {
+    match value {
+        "text" => Ok(OutputFormat::Text),
+        "json" => Ok(OutputFormat::Json),
+        _ => Err(usage_error(&format!(
+            "Invalid value \"{}\" for \"{}\": expected text or json",
+            value, key
+        ))),
+    }
+}
+
+fn verbosity(value: &str) -> Result<Verbosity> {
+    match value {
+        "quiet" => Ok(Verbosity::Quiet),
+        "normal" => Ok(Verbosity::Normal),
+        "verbose" => Ok(Verbosity::Verbose),
+        "very-verbose" => Ok(Verbosity::VeryVerbose),
+        _ => Err(usage_error(&format!(
+            "Invalid value \"{}\" for \"verbosity\": expected quiet, normal, verbose or very-verbose",
+            value
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{configuration, TempDirectory};
+    use std::fs;
+
+    #[test]
+    fn the_command_line_overrides_the_profile_and_the_file() {
+        let directory = TempDirectory::new();
+        let file = directory.write(
+            CONFIGURATION_FILE,
+            "source = \"src\"\n\
+             destination = \"out\"\n\
+             jobs = 2\n\
+             [profile.release]\n\
+             jobs = 3\n\
+             lower = true\n\
+             verbosity = \"quiet\"\n",
+        );
+        fs::create_dir_all(directory.join("src")).unwrap();
+        let file = file.to_string_lossy();
+
+        let profile = configuration(&["--config", &file, "--profile", "release"]);
+        assert_eq!(profile.jobs(), 3);
+        assert!(profile.lower());
+        assert!(profile.verbosity() == Verbosity::Quiet);
+        assert_eq!(
+            profile.origin("jobs"),
+            format!("{} [profile release]", file)
+        );
+        assert_eq!(profile.origin("source"), file);
+
+        let command_line = configuration(&[
+            "--config",
+            &file,
+            "--profile",
+            "release",
+            "-j",
+            "4",
+            "--verbose",
+        ]);
+        assert_eq!(command_line.jobs(), 4);
+        assert!(command_line.verbosity() == Verbosity::Verbose);
+        assert_eq!(command_line.origin("jobs"), "command line");
+        assert_eq!(command_line.origin("verbosity"), "command line");
+        assert_eq!(command_line.origin("md5"), "default");
+    }
 }
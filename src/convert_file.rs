@@ -1,31 +1,411 @@
-use crate::convertions::CONVERTIONS;
-use std::path::PathBuf;
+use crate::convertions::{describe_convertion, Convertion};
+use aho_corasick::{AhoCorasick, MatchKind};
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::{
     fs,
     io::{Result, Write},
 };
 
-pub fn convert_file(entry_path: &PathBuf, dest_path: PathBuf) -> Result<()> {
-    let mut source_file_buffer = fs::read(entry_path)?;
-    let mut destination_file = fs::File::create(dest_path)?;
+pub struct Transfer {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub substitutions: usize,
+    // Filled only when the substitutions are reported.
+    pub report: Vec<Substitution>,
+}
+
+pub struct Substitution {
+    pub rule: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub struct Converter {
+    convertions: Vec<Convertion>,
+    automaton: Option<AhoCorasick>,
+}
+
+impl Converter {
+    pub fn build(convertions: Vec<Convertion>) -> Converter {
+        let automaton = if single_pass_equivalent(&convertions) {
+            AhoCorasick::builder()
+                .match_kind(MatchKind::LeftmostLongest)
+                .build(convertions.iter().map(|convertion| &convertion.0))
+                .ok()
+        } else {
+            None
+        };
+
+        Converter {
+            convertions,
+            automaton,
+        }
+    }
+
+    // Returns the converted buffer and the number of substitutions made.
+    pub fn convert(&self, buffer: &[u8]) -> (Vec<u8>, usize) {
+        let mut substitutions = 0;
+        let converted = self.apply(buffer, |_, _, _, _| substitutions += 1);
+
+        (converted, substitutions)
+    }
+
+    // Also returns where each substitution was made. Lines and columns are those
+    // of the buffer the entry was applied to, which is the file itself unless an
+    // earlier entry changed it first.
+    pub fn convert_report(&self, buffer: &[u8]) -> (Vec<u8>, Vec<Substitution>) {
+        let mut report = Vec::new();
+        let mut locator = Locator::default();
+        let converted = self.apply(buffer, |pass, rule, input, offset| {
+            let (line, column) = locator.locate(pass, input, offset);
+            report.push(Substitution { rule, line, column });
+        });
 
-    for convertion in CONVERTIONS {
-        convert_buffer(&mut source_file_buffer, convertion.0, convertion.1);
+        (converted, report)
     }
 
-    destination_file.write_all(&source_file_buffer)?;
+    pub fn describe(&self, rule: usize) -> String {
+        describe_convertion(&self.convertions[rule])
+    }
 
-    Ok(())
+    // Calls found with the pass, the entry, the buffer it was applied to and
+    // the offset of every substitution.
+    fn apply(&self, buffer: &[u8], mut found: impl FnMut(usize, usize, &[u8], usize)) -> Vec<u8> {
+        match &self.automaton {
+            Some(automaton) => {
+                let mut converted = Vec::with_capacity(buffer.len());
+                automaton.replace_all_with_bytes(buffer, &mut converted, |matched, _, dst| {
+                    let rule = matched.pattern().as_usize();
+                    dst.extend_from_slice(&self.convertions[rule].1);
+                    found(0, rule, buffer, matched.start());
+                    true
+                });
+                converted
+            }
+            None => {
+                let mut converted = buffer.to_vec();
+                for (rule, convertion) in self.convertions.iter().enumerate() {
+                    converted = convert_buffer(
+                        &converted,
+                        &convertion.0,
+                        &convertion.1,
+                        |input, offset| found(rule, rule, input, offset),
+                    );
+                }
+                converted
+            }
+        }
+    }
 }
 
-fn convert_buffer(buffer: &mut Vec<u8>, from: &[u8], to: &[u8]) {
+// Groups the substitutions by entry, in the order of the conversion table.
+pub fn fired_rules(report: &[Substitution]) -> BTreeMap<usize, Vec<(usize, usize)>> {
+    let mut rules: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
+
+    for substitution in report {
+        rules
+            .entry(substitution.rule)
+            .or_default()
+            .push((substitution.line, substitution.column));
+    }
+
+    rules
+}
+
+pub fn describe_positions(rule: &str, positions: &[(usize, usize)]) -> String {
+    format!(
+        "{}: {} at {}",
+        rule,
+        positions.len(),
+        positions
+            .iter()
+            .map(|(line, column)| format!("{}:{}", line, column))
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+pub fn convert_file(
+    converter: &Converter,
+    entry_path: &Path,
+    dest_path: &Path,
+    report: bool,
+) -> Result<Transfer> {
+    let source_file_buffer = fs::read(entry_path)?;
+    let mut destination_file = fs::File::create(dest_path)?;
+    let (converted, substitutions, report) = if report {
+        let (converted, report) = converter.convert_report(&source_file_buffer);
+        (converted, report.len(), report)
+    } else {
+        let (converted, substitutions) = converter.convert(&source_file_buffer);
+        (converted, substitutions, Vec::new())
+    };
+
+    destination_file.write_all(&converted)?;
+
+    Ok(Transfer {
+        bytes_read: source_file_buffer.len() as u64,
+        bytes_written: converted.len() as u64,
+        substitutions,
+        report,
+    })
+}
+
+pub fn copy_file(entry_path: &Path, dest_path: &Path) -> Result<Transfer> {
+    let bytes = fs::copy(entry_path, dest_path)?;
+
+    Ok(Transfer {
+        bytes_read: bytes,
+        bytes_written: bytes,
+        substitutions: 0,
+        report: Vec::new(),
+    })
+}
+
+#[derive(Default)]
+struct Locator {
+    pass: usize,
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl Locator {
+    // Substitutions come in order within a pass, so the lines are counted once.
+    fn locate(&mut self, pass: usize, input: &[u8], offset: usize) -> (usize, usize) {
+        if self.line == 0 || pass != self.pass {
+            *self = Locator {
+                pass,
+                offset: 0,
+                line: 1,
+                line_start: 0,
+            };
+        }
+
+        for (index, byte) in input[self.offset..offset].iter().enumerate() {
+            if *byte == b'\n' {
+                self.line += 1;
+                self.line_start = self.offset + index + 1;
+            }
+        }
+
+        self.offset = offset;
+        (self.line, offset - self.line_start + 1)
+    }
+}
+
+fn convert_buffer(
+    buffer: &[u8],
+    from: &[u8],
+    to: &[u8],
+    mut found: impl FnMut(&[u8], usize),
+) -> Vec<u8> {
+    if from.is_empty() {
+        return buffer.to_vec();
+    }
+
+    let mut converted = Vec::with_capacity(buffer.len());
     let mut i = 0;
     while i < buffer.len() {
         if buffer[i..].starts_with(from) {
-            buffer.splice(i..i + from.len(), to.iter().cloned());
-            i += to.len();
+            converted.extend_from_slice(to);
+            found(buffer, i);
+            i += from.len();
         } else {
+            converted.push(buffer[i]);
             i += 1;
         }
     }
+    converted
+}
+
+// Applying the convertions one after the other only gives the same result as a
+// single leftmost-longest pass when no pattern can overlap another pattern or the
+// replacement written by an earlier convertion, and no earlier convertion deletes
+// what it matches. Any later pattern found in the output of an earlier pass then
+// either lies outside the replacements or overlaps one, which is checked.
+fn single_pass_equivalent(convertions: &[Convertion]) -> bool {
+    for (i, (from, _)) in convertions.iter().enumerate() {
+        if from.is_empty() {
+            return false;
+        }
+
+        for (previous_from, previous_to) in &convertions[..i] {
+            // A deleted match joins its neighbours, which may then form the pattern.
+            if previous_to.is_empty()
+                || overlaps(from, previous_from)
+                || overlaps(from, previous_to)
+            {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+fn overlaps(a: &[u8], b: &[u8]) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+
+    for offset in -(b.len() as isize - 1)..a.len() as isize {
+        let start = offset.max(0) as usize;
+        let end = (offset + b.len() as isize).min(a.len() as isize) as usize;
+
+        if a[start..end] == b[(start as isize - offset) as usize..(end as isize - offset) as usize]
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convertions::default_conversion_table;
+
+    // The conversion as the baseline did it: one full pass per table entry.
+    fn sequential(convertions: &[Convertion], buffer: &[u8]) -> Vec<u8> {
+        let mut converted = buffer.to_vec();
+
+        for (from, to) in convertions {
+            if from.is_empty() {
+                continue;
+            }
+
+            let mut next = Vec::with_capacity(converted.len());
+            let mut i = 0;
+
+            while i < converted.len() {
+                if converted[i..].starts_with(from) {
+                    next.extend_from_slice(to);
+                    i += from.len();
+                } else {
+                    next.push(converted[i]);
+                    i += 1;
+                }
+            }
+
+            converted = next;
+        }
+
+        converted
+    }
+
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+
+        fn bytes(&mut self, alphabet: &[u8], max_len: usize) -> Vec<u8> {
+            let len = self.below(max_len + 1);
+            (0..len)
+                .map(|_| alphabet[self.below(alphabet.len())])
+                .collect()
+        }
+    }
+
+    fn assert_equivalent(converter: &Converter, buffer: &[u8]) {
+        let convertions = &converter.convertions;
+        let (converted, substitutions) = converter.convert(buffer);
+        let (reported, report) = converter.convert_report(buffer);
+
+        assert_eq!(
+            converted,
+            sequential(convertions, buffer),
+            "table {:?} on {:?}",
+            convertions,
+            buffer
+        );
+        assert_eq!(reported, converted);
+        assert_eq!(report.len(), substitutions);
+    }
+
+    #[test]
+    fn built_in_table_uses_a_single_pass() {
+        assert!(Converter::build(default_conversion_table())
+            .automaton
+            .is_some());
+    }
+
+    #[test]
+    fn built_in_table_matches_sequential_passes() {
+        let convertions = default_conversion_table();
+        let mut alphabet: Vec<u8> = convertions
+            .iter()
+            .flat_map(|(from, to)| from.iter().chain(to).copied())
+            .collect();
+        alphabet.extend_from_slice(b"\r\n ()chr0123456789abc");
+        let converter = Converter::build(convertions);
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+
+        for _ in 0..5_000 {
+            assert_equivalent(&converter, &random.bytes(&alphabet, 64));
+        }
+
+        assert_equivalent(&converter, b"x := chr(251)\r\n\x1a\xfb\r\r\n\n");
+    }
+
+    #[test]
+    fn adversarial_tables_match_sequential_passes() {
+        let alphabet = b"abc";
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let mut single_pass = 0;
+
+        for _ in 0..20_000 {
+            let convertions: Vec<Convertion> = (0..1 + random.below(4))
+                .map(|_| {
+                    let mut from = random.bytes(alphabet, 2);
+                    from.push(alphabet[random.below(alphabet.len())]);
+                    (from, random.bytes(alphabet, 3))
+                })
+                .collect();
+
+            let converter = Converter::build(convertions);
+
+            if converter.automaton.is_some() {
+                single_pass += 1;
+            }
+
+            for _ in 0..8 {
+                assert_equivalent(&converter, &random.bytes(alphabet, 16));
+            }
+        }
+
+        // Both paths must have been exercised.
+        assert!(single_pass > 1_000);
+    }
+
+    #[test]
+    fn deleting_entry_falls_back_to_sequential_passes() {
+        let convertions = vec![(b"Z".to_vec(), Vec::new()), (b"ab".to_vec(), b"X".to_vec())];
+
+        assert!(Converter::build(convertions.clone()).automaton.is_none());
+        assert_eq!(Converter::build(convertions).convert(b"aZb\n").0, b"X\n");
+    }
+
+    #[test]
+    fn substitutions_are_located_by_line_and_column() {
+        let converter = Converter::build(default_conversion_table());
+        let (_, report) = converter.convert_report(b"one\r\nx := chr(251)\r\n");
+        let positions: Vec<(usize, usize, usize)> = report
+            .iter()
+            .map(|substitution| (substitution.rule, substitution.line, substitution.column))
+            .collect();
+
+        assert_eq!(positions, vec![(0, 1, 4), (2, 2, 6), (0, 2, 14)]);
+    }
 }
@@ -1,3 +1,9 @@
+use crate::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub type Convertion = (Vec<u8>, Vec<u8>);
+
 pub const CONVERTIONS: [(&[u8], &[u8]); 38] = [
     (&[13, 10], &[10]),
     (&[26], &[32]),
@@ -39,3 +45,217 @@ pub const CONVERTIONS: [(&[u8], &[u8]); 38] = [
     (&[166], ".".as_bytes()),
     (&[167], ".".as_bytes()),
 ];
+
+pub fn default_conversion_table() -> Vec<Convertion> {
+    CONVERTIONS
+        .iter()
+        .map(|convertion| (convertion.0.to_vec(), convertion.1.to_vec()))
+        .collect()
+}
+
+pub fn read_conversion_table(file: &Path) -> Result<Vec<Convertion>, Error> {
+    let contents = fs::read_to_string(file).map_err(|err| {
+        Error::Conversion(format!("Failed to read conversion table {:?}: {}", file, err))
+    })?;
+
+    parse_conversion_table(&contents)
+        .map_err(|err| Error::Conversion(format!("Invalid conversion table {:?}: {}", file, err)))
+}
+
+pub fn parse_conversion_table(contents: &str) -> Result<Vec<Convertion>, String> {
+    let mut convertions = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let mut tokens = tokenize(line).map_err(|err| format!("line {}: {}", number + 1, err))?;
+
+        if tokens.is_empty() {
+            continue;
+        }
+
+        let arrow = tokens
+            .iter()
+            .position(|token| *token == Token::Arrow)
+            .ok_or(format!("line {}: missing \"->\"", number + 1))?;
+        let to = concat_tokens(tokens.split_off(arrow + 1))
+            .map_err(|err| format!("line {}: {}", number + 1, err))?;
+        tokens.pop();
+        let from = concat_tokens(tokens).map_err(|err| format!("line {}: {}", number + 1, err))?;
+
+        if from.is_empty() {
+            return Err(format!("line {}: empty pattern", number + 1));
+        }
+
+        convertions.push((from, to));
+    }
+
+    Ok(convertions)
+}
+
+pub fn dump_conversion_table(convertions: &[Convertion]) -> String {
+    let mut contents = String::from(
+        "# exportbranch conversion table\n# <bytes> -> <bytes>, bytes as hex (0d 0a or 0d0a) or quoted strings (\"chr(42)\")\n",
+    );
+
+    for convertion in convertions {
+        contents.push_str(&format!("{}\n", describe_convertion(convertion)));
+    }
+
+    contents
+}
+
+pub fn describe_convertion((from, to): &Convertion) -> String {
+    format!("{} -> {}", dump_bytes(from), dump_bytes(to))
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Bytes(Vec<u8>),
+    Arrow,
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut characters = line.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        match character {
+            '#' => break,
+            ' ' | '\t' => {}
+            '-' if characters.peek() == Some(&'>') => {
+                characters.next();
+                tokens.push(Token::Arrow);
+            }
+            '"' => {
+                let mut bytes = Vec::new();
+
+                loop {
+                    match characters.next() {
+                        Some('"') => break,
+                        Some('\\') => match characters.next() {
+                            Some('x') => {
+                                let hex: String = characters.by_ref().take(2).collect();
+                                bytes.push(parse_hex(&hex)?);
+                            }
+                            Some(escaped @ ('"' | '\\')) => bytes.push(escaped as u8),
+                            Some(escaped) => return Err(format!("invalid escape \"\\{}\"", escaped)),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(quoted) if quoted.is_ascii() => bytes.push(quoted as u8),
+                        Some(quoted) => {
+                            return Err(format!("non-ASCII character {:?}, use \\x escapes", quoted))
+                        }
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+
+                tokens.push(Token::Bytes(bytes));
+            }
+            _ => {
+                let mut hex = character.to_string();
+
+                while let Some(next) = characters.peek() {
+                    if next.is_ascii_hexdigit() {
+                        hex.push(characters.next().unwrap());
+                    } else {
+                        break;
+                    }
+                }
+
+                // A run of hex digits such as 0d0a is read as one byte per pair.
+                if hex.len() % 2 != 0 {
+                    return Err(format!("invalid hex byte \"{}\"", hex));
+                }
+
+                let bytes = hex
+                    .as_bytes()
+                    .chunks(2)
+                    .map(|pair| parse_hex(std::str::from_utf8(pair).unwrap()))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                tokens.push(Token::Bytes(bytes));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn concat_tokens(tokens: Vec<Token>) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+
+    for token in tokens {
+        match token {
+            Token::Bytes(mut token_bytes) => bytes.append(&mut token_bytes),
+            Token::Arrow => return Err("more than one \"->\"".to_string()),
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn parse_hex(hex: &str) -> Result<u8, String> {
+    if hex.len() != 2 {
+        return Err(format!("invalid hex byte \"{}\"", hex));
+    }
+
+    u8::from_str_radix(hex, 16).map_err(|_| format!("invalid hex byte \"{}\"", hex))
+}
+
+fn dump_bytes(bytes: &[u8]) -> String {
+    if !bytes.is_empty() && bytes.iter().all(|byte| (0x20..0x7f).contains(byte)) {
+        let mut quoted = String::from("\"");
+
+        for byte in bytes {
+            if *byte == b'"' || *byte == b'\\' {
+                quoted.push('\\');
+            }
+            quoted.push(*byte as char);
+        }
+
+        quoted.push('"');
+        return quoted;
+    }
+
+    if bytes.is_empty() {
+        return "\"\"".to_string();
+    }
+
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_runs_are_read_as_byte_pairs() {
+        let convertions = parse_conversion_table("0d0a -> 0a\n0d 0a -> \"\\x0a\"\n")
+            .ok()
+            .unwrap();
+
+        assert_eq!(
+            convertions,
+            vec![
+                (vec![0x0d, 0x0a], vec![0x0a]),
+                (vec![0x0d, 0x0a], vec![0x0a])
+            ]
+        );
+        assert!(parse_conversion_table("0d0 -> 0a").is_err());
+        assert!(parse_conversion_table("0g -> 0a").is_err());
+    }
+
+    #[test]
+    fn dumped_table_is_read_back() {
+        let table = default_conversion_table();
+
+        assert_eq!(
+            parse_conversion_table(&dump_conversion_table(&table))
+                .ok()
+                .unwrap(),
+            table
+        );
+    }
+}
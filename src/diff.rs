@@ -0,0 +1,375 @@
+use crate::configuration::DiffFormat;
+
+const CONTEXT: usize = 3;
+const SIDE_WIDTH: usize = 60;
+// Above this many line pairs, differing files are shown as a whole replacement.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+#[derive(Clone, Copy)]
+enum Op {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+pub fn diff(format: DiffFormat, old: &[u8], new: &[u8], old_name: &str, new_name: &str) -> String {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    if ops.iter().all(|op| matches!(op, Op::Same(_, _))) {
+        return "no changes\n".to_string();
+    }
+
+    let mut output = match format {
+        DiffFormat::Unified => format!("--- {}\n+++ {}\n", old_name, new_name),
+        DiffFormat::SideBySide => format!(
+            "{:>5} {:<width$}   {:>5} {}\n",
+            "",
+            old_name,
+            "",
+            new_name,
+            width = SIDE_WIDTH
+        ),
+    };
+
+    for (start, end) in hunks(&ops) {
+        output.push_str(&hunk_header(&ops, start, end));
+
+        for block in blocks(&ops[start..end]) {
+            match format {
+                DiffFormat::Unified => unified_block(&mut output, &block, &old_lines, &new_lines),
+                DiffFormat::SideBySide => {
+                    side_by_side_block(&mut output, &block, &old_lines, &new_lines)
+                }
+            }
+        }
+    }
+
+    output
+}
+
+fn lines(buffer: &[u8]) -> Vec<&[u8]> {
+    let mut lines: Vec<&[u8]> = buffer.split(|byte| *byte == b'\n').collect();
+
+    if lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    lines
+}
+
+// Conversions rarely add or remove lines, so files with the same number of
+// lines are compared line by line.
+fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<Op> {
+    if old.len() == new.len() {
+        return (0..old.len())
+            .flat_map(|index| match old[index] == new[index] {
+                true => vec![Op::Same(index, index)],
+                false => vec![Op::Removed(index), Op::Added(index)],
+            })
+            .collect();
+    }
+
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    let mut ops: Vec<Op> = (0..prefix).map(|index| Op::Same(index, index)).collect();
+    ops.extend(lcs(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        prefix,
+    ));
+    ops.extend(
+        (0..suffix).map(|index| Op::Same(old.len() - suffix + index, new.len() - suffix + index)),
+    );
+    ops
+}
+
+fn lcs(old: &[&[u8]], new: &[&[u8]], offset: usize) -> Vec<Op> {
+    if old.len() * new.len() > MAX_LCS_CELLS {
+        return (0..old.len())
+            .map(|index| Op::Removed(offset + index))
+            .chain((0..new.len()).map(|index| Op::Added(offset + index)))
+            .collect();
+    }
+
+    let width = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = match old[i] == new[j] {
+                true => lengths[(i + 1) * width + j + 1] + 1,
+                false => lengths[(i + 1) * width + j].max(lengths[i * width + j + 1]),
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Same(offset + i, offset + j));
+            i += 1;
+            j += 1;
+        } else if j < new.len()
+            && (i == old.len() || lengths[i * width + j + 1] >= lengths[(i + 1) * width + j])
+        {
+            ops.push(Op::Added(offset + j));
+            j += 1;
+        } else {
+            ops.push(Op::Removed(offset + i));
+            i += 1;
+        }
+    }
+
+    ops
+}
+
+fn hunks(ops: &[Op]) -> Vec<(usize, usize)> {
+    let mut hunks = Vec::new();
+    let mut index = 0;
+
+    while index < ops.len() {
+        if matches!(ops[index], Op::Same(_, _)) {
+            index += 1;
+            continue;
+        }
+
+        let start = index.saturating_sub(CONTEXT);
+        let mut last_change = index;
+
+        while index < ops.len() {
+            if !matches!(ops[index], Op::Same(_, _)) {
+                last_change = index;
+            } else if index - last_change > 2 * CONTEXT {
+                break;
+            }
+            index += 1;
+        }
+
+        let end = (last_change + CONTEXT + 1).min(ops.len());
+        hunks.push((start, end));
+        index = end;
+    }
+
+    hunks
+}
+
+fn hunk_header(ops: &[Op], start: usize, end: usize) -> String {
+    let (mut old_start, mut new_start) = (None, None);
+    let (mut old_count, mut new_count) = (0, 0);
+
+    for op in &ops[start..end] {
+        let (old, new) = match *op {
+            Op::Same(old, new) => (Some(old), Some(new)),
+            Op::Removed(old) => (Some(old), None),
+            Op::Added(new) => (None, Some(new)),
+        };
+
+        if let Some(old) = old {
+            old_start.get_or_insert(old + 1);
+            old_count += 1;
+        }
+
+        if let Some(new) = new {
+            new_start.get_or_insert(new + 1);
+            new_count += 1;
+        }
+    }
+
+    format!(
+        "@@ -{},{} +{},{} @@\n",
+        old_start.unwrap_or(0),
+        old_count,
+        new_start.unwrap_or(0),
+        new_count
+    )
+}
+
+// Splits a hunk into runs of unchanged lines and runs of changes, with the
+// removed lines of a change before the added ones.
+fn blocks(ops: &[Op]) -> Vec<Vec<Op>> {
+    let mut blocks: Vec<Vec<Op>> = Vec::new();
+
+    for op in ops {
+        let same = matches!(op, Op::Same(_, _));
+
+        match blocks.last_mut() {
+            Some(block) if matches!(block[0], Op::Same(_, _)) == same => block.push(*op),
+            _ => blocks.push(vec![*op]),
+        }
+    }
+
+    for block in &mut blocks {
+        block.sort_by_key(|op| matches!(op, Op::Added(_)));
+    }
+
+    blocks
+}
+
+fn unified_block(output: &mut String, block: &[Op], old: &[&[u8]], new: &[&[u8]]) {
+    for op in block {
+        let (marker, line) = match *op {
+            Op::Same(old_index, _) => (' ', old[old_index]),
+            Op::Removed(old_index) => ('-', old[old_index]),
+            Op::Added(new_index) => ('+', new[new_index]),
+        };
+
+        output.push_str(&format!("{}{}\n", marker, display(line)));
+    }
+}
+
+fn side_by_side_block(output: &mut String, block: &[Op], old: &[&[u8]], new: &[&[u8]]) {
+    let removed: Vec<usize> = block
+        .iter()
+        .filter_map(|op| match *op {
+            Op::Same(old_index, _) | Op::Removed(old_index) => Some(old_index),
+            Op::Added(_) => None,
+        })
+        .collect();
+    let added: Vec<usize> = block
+        .iter()
+        .filter_map(|op| match *op {
+            Op::Same(_, new_index) | Op::Added(new_index) => Some(new_index),
+            Op::Removed(_) => None,
+        })
+        .collect();
+    let same = matches!(block[0], Op::Same(_, _));
+
+    for row in 0..removed.len().max(added.len()) {
+        let left = removed.get(row);
+        let right = added.get(row);
+        let marker = match (same, left, right) {
+            (true, _, _) => ' ',
+            (_, Some(_), Some(_)) => '|',
+            (_, Some(_), None) => '<',
+            _ => '>',
+        };
+
+        output.push_str(&format!(
+            "{:>5} {:<width$} {} {:>5} {}\n",
+            left.map(|index| (index + 1).to_string())
+                .unwrap_or_default(),
+            truncate(&left.map(|index| display(old[*index])).unwrap_or_default()),
+            marker,
+            right
+                .map(|index| (index + 1).to_string())
+                .unwrap_or_default(),
+            truncate(&right.map(|index| display(new[*index])).unwrap_or_default()),
+            width = SIDE_WIDTH
+        ));
+    }
+}
+
+// Long lines are cut to the column width and marked with a ~.
+fn truncate(line: &str) -> String {
+    if line.chars().count() <= SIDE_WIDTH {
+        return line.to_string();
+    }
+
+    let mut truncated: String = line.chars().take(SIDE_WIDTH - 1).collect();
+    truncated.push('~');
+    truncated
+}
+
+// Shows the bytes a conversion usually changes: control characters and bytes
+// that are not valid UTF-8 are escaped.
+fn display(line: &[u8]) -> String {
+    let mut displayed = String::with_capacity(line.len());
+
+    for chunk in line.utf8_chunks() {
+        for character in chunk.valid().chars() {
+            match character {
+                '\t' => displayed.push_str("    "),
+                '\r' => displayed.push_str("\\r"),
+                character if character.is_control() => {
+                    displayed.push_str(&format!("\\x{:02x}", character as u32))
+                }
+                character => displayed.push(character),
+            }
+        }
+
+        for byte in chunk.invalid() {
+            displayed.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+
+    displayed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &[u8] = b"a\nb\nc\n";
+    const NEW: &[u8] = b"a\nB\r\nc\nd\xa2\n";
+
+    #[test]
+    fn unified_hunks_show_removed_lines_before_added_ones() {
+        assert_eq!(
+            diff(DiffFormat::Unified, OLD, NEW, "old", "new"),
+            "--- old\n+++ new\n@@ -1,3 +1,4 @@\n a\n-b\n+B\\r\n c\n+d\\xa2\n"
+        );
+        assert_eq!(
+            diff(DiffFormat::Unified, OLD, OLD, "old", "new"),
+            "no changes\n"
+        );
+    }
+
+    #[test]
+    fn unified_hunks_keep_three_lines_of_context() {
+        let old: String = (1..=20).map(|line| format!("{}\n", line)).collect();
+        let new = old.replace("\n10\n", "\nten\n");
+
+        assert_eq!(
+            diff(
+                DiffFormat::Unified,
+                old.as_bytes(),
+                new.as_bytes(),
+                "old",
+                "new"
+            ),
+            "--- old\n+++ new\n@@ -7,7 +7,7 @@\n 7\n 8\n 9\n-10\n+ten\n 11\n 12\n 13\n"
+        );
+    }
+
+    #[test]
+    fn side_by_side_rows_pair_the_changed_lines() {
+        let long = "x".repeat(SIDE_WIDTH + 10);
+        let new = [NEW, long.as_bytes(), b"\n"].concat();
+        let output = diff(DiffFormat::SideBySide, OLD, &new, "old", "new");
+        let rows: Vec<String> = output
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .collect();
+
+        assert_eq!(
+            rows,
+            [
+                "old new".to_string(),
+                "@@ -1,3 +1,5 @@".to_string(),
+                "1 a 1 a".to_string(),
+                "2 b | 2 B\\r".to_string(),
+                "3 c 3 c".to_string(),
+                "> 4 d\\xa2".to_string(),
+                format!("> 5 {}~", "x".repeat(SIDE_WIDTH - 1)),
+            ]
+        );
+        assert_eq!(
+            output.lines().nth(2).unwrap(),
+            format!("{:>5} {:<width$}   {:>5} a", 1, "a", 1, width = SIDE_WIDTH)
+        );
+    }
+}
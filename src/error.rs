@@ -0,0 +1,84 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub enum Error {
+    // Invalid command line, exportbranch.toml or .exb directive.
+    Config(String),
+    Io(PathBuf, io::Error),
+    Pattern(String),
+    Conversion(String),
+    Git(String),
+    Watch(String),
+    // The number of files that could not be exported.
+    Failed(usize),
+    // The number of files found missing, different or extra by verify.
+    Mismatch(usize),
+    // An error in a configuration file, with the line it was found on.
+    Located(PathBuf, Option<usize>, Box<Error>),
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    pub fn io(path: &Path, err: io::Error) -> Error {
+        Error::Io(path.to_path_buf(), err)
+    }
+
+    pub fn at(file: &Path, line: Option<usize>, err: Error) -> Error {
+        Error::Located(file.to_path_buf(), line, Box::new(err))
+    }
+
+    pub fn not_utf8(path: &Path) -> Error {
+        Error::io(
+            path,
+            io::Error::new(io::ErrorKind::InvalidData, "path is not valid UTF-8"),
+        )
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Io(_, _) => 3,
+            Error::Pattern(_) => 4,
+            Error::Conversion(_) => 5,
+            Error::Git(_) => 6,
+            Error::Watch(_) => 7,
+            Error::Failed(_) => 1,
+            Error::Mismatch(_) => 8,
+            Error::Located(_, _, err) => err.exit_code(),
+            Error::Multiple(errors) => errors.first().map_or(1, Error::exit_code),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(message)
+            | Error::Pattern(message)
+            | Error::Conversion(message)
+            | Error::Git(message)
+            | Error::Watch(message) => write!(f, "{}", message),
+            Error::Failed(failed) => write!(f, "{} files could not be exported", failed),
+            Error::Mismatch(mismatched) => {
+                write!(f, "{} files do not match the source", mismatched)
+            }
+            Error::Io(path, err) => write!(f, "{}: {}", path.to_string_lossy(), err),
+            Error::Located(file, Some(line), err) => {
+                write!(f, "{}:{}: {}", file.to_string_lossy(), line, err)
+            }
+            Error::Located(file, None, err) => write!(f, "{}: {}", file.to_string_lossy(), err),
+            Error::Multiple(errors) => {
+                for (index, err) in errors.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::configuration::DiffFormat;
+use crate::convert_file::{describe_positions, fired_rules};
+use crate::diff::diff;
+use crate::error::{Error, Result};
+use crate::export::format_lower;
+use crate::export_branch::ExportBranch;
+use crate::export_branch_files::check_configuration_files;
+use crate::file_pattern::{deciding_exclusion, matching_pattern};
+use crate::git_files::GitStatus;
+use crate::ignore_files::deciding_ignore;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Walks from the source down to the file the way the export does, reporting
+// which .exb files were read and which pattern or setting decided each step.
+// With a diff format, a converted file is also converted in memory and compared
+// with the source.
+pub fn explain(
+    exportbranch: &ExportBranch,
+    file: &Path,
+    destinations: &[PathBuf],
+    diff_format: Option<DiffFormat>,
+) -> Result<String> {
+    let configuration = exportbranch.configuration;
+    let source = &*exportbranch.source;
+
+    if !file.is_file() {
+        return Err(Error::Config(format!("{:?} is not a file", file)));
+    }
+
+    let relative = file
+        .strip_prefix(source)
+        .map_err(|_| Error::Config(format!("{:?} is not inside the source {:?}", file, source)))?;
+
+    let mut filters = exportbranch.filters()?;
+    let git_files = exportbranch.read_git_files()?;
+    let mut directory = source.clone();
+    let mut explanation = Vec::new();
+    let mut skipped = None;
+
+    explanation.push(("file", file.to_string_lossy().to_string()));
+    explanation.push(("source", source.to_string_lossy().to_string()));
+
+    for component in relative.iter() {
+        filters = check_configuration_files(
+            &directory,
+            &filters,
+            configuration.ignore_case(),
+            configuration.invalid_exb(),
+        )?;
+        directory = directory.join(component);
+
+        let is_directory = directory != file;
+
+        if is_directory && filters.disregarded_directories.contains(&directory) {
+            skipped = Some(format!(
+                "disregarded directory {}",
+                directory.to_string_lossy()
+            ));
+            break;
+        }
+
+        if let Some(ignored) = &filters.ignored {
+            if is_directory && directory.file_name().is_some_and(|name| name == ".git") {
+                skipped = Some("ignored .git directory".to_string());
+                break;
+            }
+
+            if let Some(exclusion) = deciding_ignore(&directory, is_directory, ignored) {
+                if !exclusion.is_negated() {
+                    skipped = Some(format!(
+                        "{} ignored by {} ({})",
+                        directory.to_string_lossy(),
+                        exclusion.text(),
+                        exclusion.pattern().origin()
+                    ));
+                    break;
+                }
+            }
+        }
+
+        if is_directory
+            && git_files
+                .as_ref()
+                .is_some_and(|git_files| !git_files.has_tracked_files(&directory))
+        {
+            skipped = Some(format!(
+                "{} has no files tracked by git",
+                directory.to_string_lossy()
+            ));
+            break;
+        }
+    }
+
+    explanation.push((
+        "configuration",
+        match filters.configuration_files.is_empty() {
+            true => "none".to_string(),
+            false => filters
+                .configuration_files
+                .iter()
+                .map(|file| file.to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+                .join(&format!("\n{:17}", "")),
+        },
+    ));
+
+    if skipped.is_none() {
+        match matching_pattern(file, &filters.file_filters) {
+            Some(pattern) => explanation.push((
+                "filter",
+                format!("{} ({})", pattern.text(), pattern.origin()),
+            )),
+            None => skipped = Some("not matched by filters".to_string()),
+        }
+    }
+
+    if skipped.is_none() {
+        if let Some(exclusion) = deciding_exclusion(file, &filters.exclusions) {
+            explanation.push((
+                "exclusion",
+                format!("{} ({})", exclusion.text(), exclusion.pattern().origin()),
+            ));
+
+            if !exclusion.is_negated() {
+                skipped = Some("excluded".to_string());
+            }
+        }
+    }
+
+    if skipped.is_none() {
+        match git_files.as_ref().map(|git_files| git_files.status(file)) {
+            Some(GitStatus::Untracked) => skipped = Some("not tracked by git".to_string()),
+            Some(GitStatus::Unchanged) => skipped = Some("unchanged since revision".to_string()),
+            Some(GitStatus::Changed) => {
+                explanation.push(("git", "changed since revision".to_string()))
+            }
+            Some(GitStatus::Tracked) => explanation.push(("git", "tracked".to_string())),
+            None => {}
+        }
+    }
+
+    if let Some(reason) = skipped {
+        explanation.push(("action", format!("skip ({})", reason)));
+        return Ok(format_explanation(&explanation));
+    }
+
+    let mut converts = false;
+
+    if let Some(origin) = &filters.do_not_convert {
+        explanation.push(("action", format!("copy (convert = false, {})", origin)));
+    } else if let Some(pattern) = matching_pattern(file, &filters.only_copy_files) {
+        explanation.push((
+            "action",
+            format!("copy (only copy {}, {})", pattern.text(), pattern.origin()),
+        ));
+    } else {
+        converts = true;
+        explanation.push(("action", "convert".to_string()));
+        explanation.push((
+            "conversion",
+            match &filters.conversion {
+                Some(conversion) => format!(
+                    "{} ({})",
+                    conversion.table.to_string_lossy(),
+                    conversion.origin
+                ),
+                None => format!(
+                    "{} ({})",
+                    configuration.conversion_table().unwrap_or("built-in"),
+                    configuration.origin("conversion")
+                ),
+            },
+        ));
+    }
+
+    for destination in destinations {
+        explanation.push((
+            "destination",
+            format_lower(
+                destination.join(relative),
+                destination,
+                configuration.lower(),
+            )?
+            .to_string_lossy()
+            .to_string(),
+        ));
+    }
+
+    let format = match diff_format {
+        Some(format) if converts => format,
+        _ => return Ok(format_explanation(&explanation)),
+    };
+
+    let converter = match &filters.conversion {
+        Some(conversion) => &conversion.converter,
+        None => &exportbranch.converter,
+    };
+    let contents = fs::read(file).map_err(|err| Error::io(file, err))?;
+    let (converted, report) = converter.convert_report(&contents);
+
+    explanation.push((
+        "substitutions",
+        match report.is_empty() {
+            true => "none".to_string(),
+            false => fired_rules(&report)
+                .iter()
+                .map(|(rule, positions)| describe_positions(&converter.describe(*rule), positions))
+                .collect::<Vec<String>>()
+                .join(&format!("\n{:17}", "")),
+        },
+    ));
+
+    let name = file.to_string_lossy();
+
+    Ok(format!(
+        "{}\n{}",
+        format_explanation(&explanation),
+        diff(
+            format,
+            &contents,
+            &converted,
+            &name,
+            &format!("{} (converted)", name)
+        )
+    ))
+}
+
+fn format_explanation(explanation: &[(&str, String)]) -> String {
+    explanation
+        .iter()
+        .map(|(key, value)| format!("{:.<15}: {}\n", key, value))
+        .collect()
+}
@@ -1,51 +1,83 @@
-use crate::convert_file::convert_file;
+use crate::configuration::{Configuration, OutputFormat, Verbosity};
+use crate::convert_file::{describe_positions, fired_rules, Converter, Substitution};
+use crate::error::{Error, Result};
 use crate::export_branch::ExportBranch;
-use crate::export_branch_files::check_configuration_file;
-use crate::file_checker::FileStatus;
-use regex::Regex;
+use crate::export_branch_files::{check_configuration_files, Filters};
+use crate::export_jobs::{ExportJob, ExportOutcome, Pipeline};
+use crate::file_checker::UpdateReason;
+use crate::file_pattern::{is_excluded, FilePattern};
+use crate::git_files::GitStatus;
+use crate::ignore_files::is_ignored;
+use crate::json::json_string;
+use crate::log::Log;
 use std::fs;
-use std::io::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 pub fn export(
     exportbranch: &mut ExportBranch,
     source: PathBuf,
     destination: PathBuf,
-    file_filters: &Vec<Regex>,
-    only_copy_files: &Vec<Regex>,
+    filters: &Filters,
 ) -> Result<()> {
-    let (file_filters_regex, only_copy_files_regex) =
-        check_configuration_file(&source, file_filters.to_owned(), only_copy_files.to_owned());
+    let filters = check_configuration_files(
+        &source,
+        filters,
+        exportbranch.configuration.ignore_case(),
+        exportbranch.configuration.invalid_exb(),
+    )?;
 
     let destination = format_lower(
         destination,
         &exportbranch.destination,
         exportbranch.configuration.lower(),
-    );
+    )?;
 
-    if !destination.exists() {
-        fs::create_dir_all(&destination)?;
+    if !destination.exists() && !exportbranch.configuration.dry_run() {
+        fs::create_dir_all(&destination).map_err(|err| Error::io(&destination, err))?;
     }
 
-    for entry in fs::read_dir(source)? {
-        let entry = entry?;
+    let mut entries = fs::read_dir(&source)
+        .and_then(|entries| entries.collect::<std::io::Result<Vec<fs::DirEntry>>>())
+        .map_err(|err| Error::io(&source, err))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
         let entry_path = entry.path();
 
+        if is_ignored_path(&filters, &entry_path) {
+            skip(exportbranch, entry_path, "ignored");
+            continue;
+        }
+
         if entry_path.is_dir() {
-            export_directory(
+            if filters.disregarded_directories.contains(&entry_path) {
+                skip(exportbranch, entry_path.clone(), "disregarded directory");
+                exportbranch.skipped_directories.push(entry_path);
+                continue;
+            }
+
+            if exportbranch
+                .git_files
+                .as_ref()
+                .is_some_and(|git_files| !git_files.has_tracked_files(&entry_path))
+            {
+                skip(exportbranch, entry_path, "not tracked by git");
+                continue;
+            }
+
+            export(
                 exportbranch,
                 entry_path,
-                &destination,
-                &file_filters_regex,
-                &only_copy_files_regex,
+                destination.join(entry.file_name()),
+                &filters,
             )?;
         } else if entry_path.is_file() {
             export_file(
                 exportbranch,
                 entry_path,
                 destination.join(entry.file_name()),
-                &file_filters_regex,
-                &only_copy_files_regex,
+                &filters,
             )?;
         }
     }
@@ -53,130 +85,521 @@ pub fn export(
     Ok(())
 }
 
-fn format_lower(destination: PathBuf, raw_destination: &PathBuf, lower: bool) -> PathBuf {
-    if !lower {
-        return destination;
+pub fn export_path(exportbranch: &mut ExportBranch, path: &Path, filters: &Filters) -> Result<()> {
+    let relative = match path.strip_prefix(&*exportbranch.source) {
+        Ok(relative) => relative.to_path_buf(),
+        _ => return Ok(()),
+    };
+
+    let mut filters = filters.clone();
+    let mut directory = *(exportbranch.source.clone());
+
+    for component in relative.iter() {
+        filters = check_configuration_files(
+            &directory,
+            &filters,
+            exportbranch.configuration.ignore_case(),
+            exportbranch.configuration.invalid_exb(),
+        )?;
+        directory = directory.join(component);
+
+        if filters.disregarded_directories.contains(&directory)
+            || is_ignored_path(&filters, &directory)
+        {
+            return Ok(());
+        }
     }
 
-    let raw = raw_destination.to_str().unwrap();
+    let destination = exportbranch.destination.join(&relative);
 
-    raw_destination
-        .join(
-            {
-                let mut formated = destination.to_str().unwrap().replace(raw, "");
+    if path.is_dir() {
+        return export(exportbranch, path.to_path_buf(), destination, &filters);
+    }
+
+    if !path.is_file() {
+        return Ok(());
+    }
 
-                if formated.starts_with(std::path::MAIN_SEPARATOR) {
-                    formated.remove(0);
-                }
+    let destination_directory = format_lower(
+        destination
+            .parent()
+            .unwrap_or(&exportbranch.destination)
+            .to_path_buf(),
+        &exportbranch.destination,
+        exportbranch.configuration.lower(),
+    )?;
 
-                formated
-            }
-            .to_lowercase(),
-        )
-        .to_path_buf()
+    if !destination_directory.exists() && !exportbranch.configuration.dry_run() {
+        fs::create_dir_all(&destination_directory)
+            .map_err(|err| Error::io(&destination_directory, err))?;
+    }
+
+    export_file(exportbranch, path.to_path_buf(), destination, &filters)
+}
+
+pub fn start_jobs(exportbranch: &mut ExportBranch) {
+    let configuration = exportbranch.configuration;
+
+    exportbranch.pipeline = Some(Pipeline::start(
+        configuration.jobs(),
+        exportbranch.file_checker.mode(),
+        Arc::clone(&exportbranch.converter),
+        configuration.dry_run(),
+        configuration.report_conversions(),
+    ));
+    exportbranch.merged = 0;
+    exportbranch.skipped.clear();
+    exportbranch.log.start_progress();
+}
+
+// Waits for the jobs still running and merges their outcomes.
+pub fn finish_jobs(exportbranch: &mut ExportBranch) -> Result<()> {
+    let finished = match &mut exportbranch.pipeline {
+        Some(pipeline) => pipeline.finish(),
+        None => return Ok(()),
+    };
+    let merged = merge_outcomes(exportbranch, finished);
+    print_skipped_paths(exportbranch, usize::MAX);
+
+    exportbranch.pipeline = None;
+    exportbranch.log.finish_progress();
+    merged
 }
 
-fn export_directory(
+fn merge_outcomes(
     exportbranch: &mut ExportBranch,
-    source: PathBuf,
-    destination: &PathBuf,
-    file_filters: &Vec<Regex>,
-    only_copy_files: &Vec<Regex>,
+    finished: Vec<(ExportJob, ExportOutcome)>,
 ) -> Result<()> {
-    let entry_file_name = source.file_name().unwrap();
-    let dest_path = destination.join(entry_file_name);
-    export(
-        exportbranch,
-        source.to_path_buf(),
-        dest_path,
-        file_filters,
-        only_copy_files,
-    )
+    for (job, outcome) in finished {
+        print_skipped_paths(exportbranch, exportbranch.merged);
+        merge_outcome(exportbranch, &job, outcome)?;
+        exportbranch.merged += 1;
+    }
+
+    Ok(())
+}
+
+// With --dry-run or -vv the skipped paths are listed in walk order, so each
+// waits for the outcomes of the jobs submitted before it.
+fn skip(exportbranch: &mut ExportBranch, path: PathBuf, reason: &'static str) {
+    let submitted = match &exportbranch.pipeline {
+        Some(pipeline) if exportbranch.configuration.report_skipped() => pipeline.submitted(),
+        _ => return,
+    };
+
+    exportbranch.skipped.push_back((submitted, path, reason));
+    print_skipped_paths(exportbranch, exportbranch.merged);
+}
+
+fn print_skipped_paths(exportbranch: &mut ExportBranch, merged: usize) {
+    while let Some((submitted, _, _)) = exportbranch.skipped.front() {
+        if *submitted > merged {
+            break;
+        }
+
+        if let Some((_, path, reason)) = exportbranch.skipped.pop_front() {
+            print_skipped(
+                &mut exportbranch.log,
+                exportbranch.configuration,
+                &path,
+                reason,
+            );
+        }
+    }
+}
+
+fn merge_outcome(
+    exportbranch: &mut ExportBranch,
+    job: &ExportJob,
+    outcome: ExportOutcome,
+) -> Result<()> {
+    let configuration = exportbranch.configuration;
+    let file_checker = &mut exportbranch.file_checker;
+    let summary = &mut exportbranch.summary;
+    let log = &mut exportbranch.log;
+
+    log.advance_progress();
+
+    match outcome {
+        ExportOutcome::UpToDate => {
+            summary.up_to_date += 1;
+            print_skipped(log, configuration, &job.source_file, "up to date");
+            return Ok(());
+        }
+        ExportOutcome::DryRun(reason) => {
+            print_dry_run(
+                log,
+                configuration.output(),
+                if job.only_copy { "copy" } else { "convert" },
+                &job.source_file,
+                Some(&job.destination_file),
+                reason.describe(),
+            );
+            return Ok(());
+        }
+        ExportOutcome::Exported {
+            source_metadata,
+            output,
+            transfer,
+            reason,
+        } => {
+            print_file(
+                log,
+                configuration,
+                job.only_copy,
+                &job.source_file,
+                &job.destination_file,
+                reason.describe(),
+            );
+            if !transfer.report.is_empty() {
+                print_conversions(
+                    log,
+                    configuration.output(),
+                    job.converter.as_deref().unwrap_or(&exportbranch.converter),
+                    job,
+                    &transfer.report,
+                );
+            }
+            summary.add_transfer(job.only_copy, &transfer);
+            file_checker.add_file(
+                &job.source_file,
+                source_metadata,
+                output,
+                &job.destination_file,
+            )?;
+        }
+        ExportOutcome::Failed(err) => {
+            print_failed(
+                log,
+                configuration.output(),
+                job.only_copy,
+                &job.source_file,
+                &job.destination_file,
+                &err.to_string(),
+            );
+            summary
+                .failed
+                .push((job.source_file.clone(), err.to_string()));
+            file_checker.remove_file(&job.source_file)?;
+        }
+    }
+
+    file_checker.checkpoint()
+}
+
+pub fn format_lower(destination: PathBuf, raw_destination: &Path, lower: bool) -> Result<PathBuf> {
+    if !lower {
+        return Ok(destination);
+    }
+
+    let formated = destination
+        .strip_prefix(raw_destination)
+        .unwrap_or(&destination)
+        .to_str()
+        .ok_or_else(|| Error::not_utf8(&destination))?;
+
+    Ok(raw_destination.join(formated.to_lowercase()))
 }
 
 fn export_file(
     exportbranch: &mut ExportBranch,
     source_file: PathBuf,
     destination_file: PathBuf,
-    file_filters: &Vec<Regex>,
-    only_copy_files: &Vec<Regex>,
+    filters: &Filters,
 ) -> Result<()> {
-    if !file_match(&source_file, file_filters) {
+    exportbranch.summary.scanned += 1;
+
+    if !file_match(&source_file, &filters.file_filters) {
+        skip(exportbranch, source_file, "not matched by filters");
         return Ok(());
     }
 
+    if is_excluded(&source_file, &filters.exclusions) {
+        skip(exportbranch, source_file.clone(), "excluded");
+        exportbranch.excluded_files.push(source_file);
+        return Ok(());
+    }
+
+    let git_status = exportbranch
+        .git_files
+        .as_ref()
+        .map(|git_files| git_files.status(&source_file));
+
     let destination_file = format_lower(
         destination_file,
         &exportbranch.destination,
         exportbranch.configuration.lower(),
-    );
+    )?;
 
-    match file_need_update(&source_file, exportbranch, &destination_file) {
-        FileStatus::UpToDate => {
+    let force = match git_status {
+        Some(GitStatus::Untracked) => {
+            skip(exportbranch, source_file, "not tracked by git");
             return Ok(());
         }
-        FileStatus::Modified(system_time) => {
-            exportbranch
-                .file_checker
-                .add_file(&source_file, system_time);
+        // The revision only says the source is as committed, not that the
+        // destination still holds it.
+        Some(GitStatus::Unchanged)
+            if !exportbranch.configuration.reload() && destination_file.exists() =>
+        {
+            exportbranch.summary.matched += 1;
+            exportbranch.summary.up_to_date += 1;
+            skip(
+                exportbranch,
+                source_file.clone(),
+                "unchanged since revision",
+            );
+            exportbranch.unchanged_files.push(source_file);
+            return Ok(());
         }
+        Some(GitStatus::Unchanged) => Some(
+            file_need_force(exportbranch, &destination_file)
+                .unwrap_or(UpdateReason::MissingDestination),
+        ),
+        Some(GitStatus::Changed) => Some(UpdateReason::GitChanged),
+        _ => file_need_force(exportbranch, &destination_file),
+    };
+
+    exportbranch.summary.matched += 1;
+
+    let job = ExportJob {
+        only_copy: filters.do_not_convert.is_some()
+            || file_match(&source_file, &filters.only_copy_files),
+        force,
+        stored: exportbranch.file_checker.stored(&source_file)?,
+        converter: filters
+            .conversion
+            .as_ref()
+            .map(|conversion| conversion.converter.clone()),
+        source_file,
+        destination_file,
+    };
+
+    if exportbranch.configuration.mirror() {
+        exportbranch.exported_files.push(job.source_file.clone());
     }
 
-    let only_copy = file_match(&source_file, only_copy_files);
+    // Without a pipeline (verify), the jobs are collected and run afterwards.
+    let finished = match &mut exportbranch.pipeline {
+        Some(pipeline) => {
+            pipeline.submit(job);
+            exportbranch.log.add_progress_total();
+            pipeline.ready()
+        }
+        None => {
+            exportbranch.jobs.push(job);
+            return Ok(());
+        }
+    };
 
-    print_file(only_copy, &source_file, &destination_file);
+    merge_outcomes(exportbranch, finished)
+}
 
-    if only_copy {
-        match fs::copy(&source_file, destination_file) {
-            Err(err) => {
-                eprint!("Error copying file: {}", err);
-                exportbranch.file_checker.remove_file(&source_file);
-            }
-            _ => {}
-        };
-    } else {
-        match convert_file(&source_file, destination_file) {
-            Err(err) => {
-                eprint!("Error copying file: {}", err);
-                exportbranch.file_checker.remove_file(&source_file);
-            }
-            _ => {}
-        };
+pub fn print_dry_run(
+    log: &mut Log,
+    output: OutputFormat,
+    action: &str,
+    source: &Path,
+    destination: Option<&Path>,
+    reason: &str,
+) {
+    let source = source_path_display(&source.to_string_lossy()).to_string();
+
+    match output {
+        OutputFormat::Text => match destination {
+            Some(destination) => log.line(&format!(
+                "{:<8} {} -> {} ({})",
+                action,
+                source,
+                destination.to_string_lossy(),
+                reason
+            )),
+            None => log.line(&format!("{:<8} {} ({})", action, source, reason)),
+        },
+        OutputFormat::Json => match destination {
+            Some(destination) => log.line(&format!(
+                "{{\"event\":\"dry_run\",\"action\":{},\"source\":{},\"destination\":{},\"reason\":{}}}",
+                json_string(action),
+                json_string(&source),
+                json_string(&destination.to_string_lossy()),
+                json_string(reason)
+            )),
+            None => log.line(&format!(
+                "{{\"event\":\"dry_run\",\"action\":{},\"source\":{},\"reason\":{}}}",
+                json_string(action),
+                json_string(&source),
+                json_string(reason)
+            )),
+        },
     }
+}
 
-    Ok(())
+// Skipped files are listed by --dry-run, and by -vv with the text output.
+pub fn print_skipped(log: &mut Log, configuration: &Configuration, path: &Path, reason: &str) {
+    if configuration.report_skipped() {
+        print_dry_run(log, configuration.output(), "skip", path, None, reason);
+    }
 }
 
-fn print_file(only_copy: bool, entry_path: &PathBuf, dest_path: &PathBuf) {
-    println!(
-        "{}\r\nsource.....: {}\r\ndestination: {}\r\n",
-        {
-            if only_copy {
-                "copying..."
-            } else {
-                "converting..."
-            }
+pub fn print_start(
+    log: &mut Log,
+    output: OutputFormat,
+    source: &Path,
+    destination: &Path,
+    dry_run: bool,
+) {
+    if output == OutputFormat::Json {
+        log.line(&format!(
+            "{{\"event\":\"start\",\"source\":{},\"destination\":{},\"dry_run\":{}}}",
+            json_string(source_path_display(&source.to_string_lossy())),
+            json_string(&destination.to_string_lossy()),
+            dry_run
+        ));
+    }
+}
+
+fn print_file(
+    log: &mut Log,
+    configuration: &Configuration,
+    only_copy: bool,
+    entry_path: &Path,
+    dest_path: &Path,
+    reason: &str,
+) {
+    let action = if only_copy { "copy" } else { "convert" };
+    let source = source_path_display(&entry_path.to_string_lossy()).to_string();
+
+    match configuration.output() {
+        OutputFormat::Text => match configuration.verbosity() {
+            Verbosity::Quiet => {}
+            Verbosity::Normal => log.line(&format!(
+                "{:<8} {} -> {}",
+                action,
+                source,
+                dest_path.to_string_lossy()
+            )),
+            _ => log.line(&format!(
+                "{:<8} {} -> {} ({})",
+                action,
+                source,
+                dest_path.to_string_lossy(),
+                reason
+            )),
         },
-        source_path_display(&entry_path.to_string_lossy()),
-        dest_path.to_string_lossy()
-    );
+        OutputFormat::Json => log.line(&format!(
+            "{{\"event\":\"file\",\"action\":{},\"source\":{},\"destination\":{},\"status\":\"exported\",\"reason\":{}}}",
+            json_string(action),
+            json_string(&source),
+            json_string(&dest_path.to_string_lossy()),
+            json_string(reason)
+        )),
+    }
+}
+
+fn print_conversions(
+    log: &mut Log,
+    output: OutputFormat,
+    converter: &Converter,
+    job: &ExportJob,
+    report: &[Substitution],
+) {
+    let rules = fired_rules(report);
+
+    match output {
+        OutputFormat::Text => {
+            // Printed at every verbosity, so the report names the file itself
+            // rather than relying on the exported line that -q leaves out.
+            log.line(&format!(
+                "{:<8} {}",
+                "report",
+                source_path_display(&job.source_file.to_string_lossy())
+            ));
+
+            for (rule, positions) in &rules {
+                log.line(&format!(
+                    "    {}",
+                    describe_positions(&converter.describe(*rule), positions)
+                ));
+            }
+        }
+        OutputFormat::Json => {
+            let rules: Vec<String> = rules
+                .iter()
+                .map(|(rule, positions)| {
+                    format!(
+                        "{{\"rule\":{},\"count\":{},\"positions\":[{}]}}",
+                        json_string(&converter.describe(*rule)),
+                        positions.len(),
+                        positions
+                            .iter()
+                            .map(|(line, column)| format!("[{},{}]", line, column))
+                            .collect::<Vec<String>>()
+                            .join(",")
+                    )
+                })
+                .collect();
+
+            log.line(&format!(
+                "{{\"event\":\"conversions\",\"source\":{},\"destination\":{},\"rules\":[{}]}}",
+                json_string(source_path_display(&job.source_file.to_string_lossy())),
+                json_string(&job.destination_file.to_string_lossy()),
+                rules.join(",")
+            ));
+        }
+    }
+}
+
+fn print_failed(
+    log: &mut Log,
+    output: OutputFormat,
+    only_copy: bool,
+    entry_path: &Path,
+    dest_path: &Path,
+    error: &str,
+) {
+    let action = if only_copy { "copy" } else { "convert" };
+    let source = source_path_display(&entry_path.to_string_lossy()).to_string();
+
+    match output {
+        OutputFormat::Text => log.error(&format!(
+            "Error exporting {} -> {}: {}",
+            source,
+            dest_path.to_string_lossy(),
+            error
+        )),
+        OutputFormat::Json => log.line(&format!(
+            "{{\"event\":\"failed\",\"action\":{},\"source\":{},\"destination\":{},\"status\":\"failed\",\"error\":{}}}",
+            json_string(action),
+            json_string(&source),
+            json_string(&dest_path.to_string_lossy()),
+            json_string(error)
+        )),
+    }
+}
+
+// For the errors that are not about one file, such as those while watching.
+pub fn print_error(log: &mut Log, output: OutputFormat, error: &str) {
+    match output {
+        OutputFormat::Text => log.error(error),
+        OutputFormat::Json => log.line(&format!(
+            "{{\"event\":\"failed\",\"status\":\"failed\",\"error\":{}}}",
+            json_string(error)
+        )),
+    }
 }
 
 #[cfg(target_os = "windows")]
-fn source_path_display(entry_path: &str) -> &str {
+pub fn source_path_display(entry_path: &str) -> &str {
     &entry_path[4..]
 }
 
 #[cfg(target_os = "linux")]
-fn source_path_display(entry_path: &str) -> &str {
+pub fn source_path_display(entry_path: &str) -> &str {
     entry_path
 }
 
-fn file_match(file: &PathBuf, file_filters: &Vec<Regex>) -> bool {
-    let file_name = file.file_name().unwrap().to_str().unwrap();
-
+fn file_match(file: &Path, file_filters: &[FilePattern]) -> bool {
     for file_filter in file_filters {
-        if file_filter.is_match(&file_name) {
+        if file_filter.is_match(file) {
             return true;
         }
     }
@@ -184,16 +607,23 @@ fn file_match(file: &PathBuf, file_filters: &Vec<Regex>) -> bool {
     false
 }
 
-fn file_need_update(
-    file: &PathBuf,
-    exportbranch: &mut ExportBranch,
-    destination_file: &PathBuf,
-) -> FileStatus {
+pub fn is_ignored_path(filters: &Filters, path: &Path) -> bool {
+    match &filters.ignored {
+        Some(ignored) => is_ignored(path, path.is_dir(), ignored),
+        None => false,
+    }
+}
+
+fn file_need_force(exportbranch: &ExportBranch, destination_file: &Path) -> Option<UpdateReason> {
     let configuration = exportbranch.configuration;
 
-    if configuration.reload() || (configuration.md5() && !destination_file.exists()) {
-        return exportbranch.file_checker.force_update(file);
+    if configuration.reload() {
+        return Some(UpdateReason::Reload);
+    }
+
+    if configuration.md5() && !destination_file.exists() {
+        return Some(UpdateReason::MissingDestination);
     }
 
-    exportbranch.file_checker.check(file)
+    None
 }
@@ -1,14 +1,40 @@
-use crate::configuration::Configuration;
-use crate::export::export;
-use crate::export_branch_files::checked_to_regex;
+use crate::configuration::{Configuration, OutputFormat, Verbosity};
+use crate::convert_file::Converter;
+use crate::error::{Error, Result};
+use crate::export::{export, export_path, finish_jobs, print_error, print_start, start_jobs};
+use crate::export_branch_files::{is_configuration_file, report_problems, Filters};
+use crate::export_jobs::{ExportJob, Pipeline};
 use crate::file_checker::FileChecker;
+use crate::git_files::GitFiles;
+use crate::log::Log;
+use crate::mirror::{mirror, prune_removed};
+use crate::summary::Summary;
+use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 pub struct ExportBranch<'a> {
     pub source: Box<PathBuf>,
     pub destination: Box<PathBuf>,
     pub configuration: &'a Configuration,
     pub file_checker: &'a mut FileChecker,
+    pub skipped_directories: Vec<PathBuf>,
+    // Only filled when there is no pipeline running the jobs.
+    pub jobs: Vec<ExportJob>,
+    pub pipeline: Option<Pipeline>,
+    // The sources of the jobs, kept for --mirror.
+    pub exported_files: Vec<PathBuf>,
+    // Skipped paths waiting to be listed, with the number of jobs submitted
+    // before them.
+    pub skipped: VecDeque<(usize, PathBuf, &'static str)>,
+    pub merged: usize,
+    pub excluded_files: Vec<PathBuf>,
+    pub unchanged_files: Vec<PathBuf>,
+    pub git_files: Option<GitFiles>,
+    pub converter: Arc<Converter>,
+    pub summary: Summary,
+    pub log: Log,
 }
 
 impl<'a> ExportBranch<'a> {
@@ -17,33 +43,268 @@ impl<'a> ExportBranch<'a> {
         destination: PathBuf,
         configuration: &'a Configuration,
         file_checker: &'a mut FileChecker,
-    ) -> ExportBranch<'a> {
-        ExportBranch {
+    ) -> Result<ExportBranch<'a>> {
+        Ok(ExportBranch {
             source: Box::new(source),
             destination: Box::new(destination.clone()),
             configuration,
             file_checker,
-        }
+            skipped_directories: Vec::new(),
+            jobs: Vec::new(),
+            pipeline: None,
+            exported_files: Vec::new(),
+            skipped: VecDeque::new(),
+            merged: 0,
+            excluded_files: Vec::new(),
+            unchanged_files: Vec::new(),
+            git_files: None,
+            converter: Arc::new(Converter::build(configuration.convertions().clone())),
+            summary: Summary::default(),
+            log: Log::open(configuration)?,
+        })
     }
 
-    pub fn perform_exporting(&mut self) {
-        let file_filters: Vec<String> = self.configuration.file_filters().clone();
-        let only_copy_files: Vec<String> = self.configuration.only_copy_files().clone();
+    // Returns the number of files that could not be exported.
+    pub fn perform_exporting(&mut self) -> Result<usize> {
+        let timer = Instant::now();
+
+        print_start(
+            &mut self.log,
+            self.configuration.output(),
+            &self.source,
+            &self.destination,
+            self.configuration.dry_run(),
+        );
+
+        let filters = self.filters()?;
+        self.git_files = self.read_git_files()?;
 
-        if let Err(err) = export(
+        start_jobs(self);
+        export(
             self,
             *(self.source.clone()),
             *(self.destination.clone()),
-            &checked_to_regex(file_filters),
-            &checked_to_regex(only_copy_files),
-        ) {
-            eprintln!("{}", err);
-            std::process::exit(1);
+            &filters,
+        )?;
+        finish_jobs(self)?;
+
+        if self.configuration.mirror() {
+            mirror(self)?;
         }
 
-        if let Err(err) = self.file_checker.save() {
-            eprintln!("{}", err);
-            std::process::exit(1);
+        if self.configuration.dry_run() {
+            return Ok(0);
+        }
+
+        self.file_checker.save()?;
+
+        if !self.configuration.report_skipped()
+            && self.configuration.output() == OutputFormat::Text
+            && self.configuration.verbosity() > Verbosity::Quiet
+        {
+            self.print_skipped_directories();
+            self.print_excluded_files();
+        }
+
+        self.summary.elapsed = timer.elapsed();
+        self.log.summary(&self.summary.format(
+            self.configuration.output(),
+            &self.source,
+            &self.destination,
+        ));
+
+        Ok(self.summary.failed.len())
+    }
+
+    pub fn export_changes(&mut self, changed: &[PathBuf]) {
+        let filters = match self.filters() {
+            Ok(filters) => filters,
+            Err(err) => {
+                self.print_error(&err);
+                return;
+            }
         };
+        let mut exported_directories: Vec<PathBuf> = Vec::new();
+
+        self.excluded_files.clear();
+        self.unchanged_files.clear();
+        self.summary = Summary::default();
+
+        self.git_files = match self.read_git_files() {
+            Ok(git_files) => git_files,
+            Err(err) => {
+                self.print_error(&err);
+                return;
+            }
+        };
+
+        start_jobs(self);
+
+        for path in changed {
+            let path = match path.parent() {
+                Some(parent) if is_configuration_file(path) => parent,
+                _ => path.as_path(),
+            };
+
+            if !path.starts_with(&*self.source)
+                || exported_directories.iter().any(|x| path.starts_with(x))
+            {
+                continue;
+            }
+
+            if path.is_dir() {
+                exported_directories.push(path.to_path_buf());
+            }
+
+            if !path.exists() {
+                if let Err(err) = prune_removed(self, path) {
+                    self.print_error(&err);
+                }
+                continue;
+            }
+
+            if let Err(err) = export_path(self, path, &filters) {
+                self.print_error(&err);
+            }
+        }
+
+        if let Err(err) = finish_jobs(self) {
+            self.print_error(&err);
+        }
+
+        if self.configuration.dry_run() {
+            return;
+        }
+
+        if let Err(err) = self.file_checker.save() {
+            self.print_error(&err);
+        }
+    }
+
+    fn print_error(&mut self, err: &Error) {
+        print_error(&mut self.log, self.configuration.output(), &err.to_string());
+    }
+
+    pub fn filters(&self) -> Result<Filters> {
+        let mut problems = Vec::new();
+        let filters = Filters::build(self.configuration, &self.source, &mut problems)?;
+
+        report_problems(problems, self.configuration.invalid_exb())?;
+
+        Ok(filters)
+    }
+
+    pub fn read_git_files(&self) -> Result<Option<GitFiles>> {
+        if !self.configuration.tracked() {
+            return Ok(None);
+        }
+
+        GitFiles::read(&self.source, self.configuration.since())
+            .map(Some)
+            .map_err(Error::Git)
+    }
+
+    fn print_skipped_directories(&mut self) {
+        if self.skipped_directories.is_empty() {
+            return;
+        }
+
+        self.log.line("Skipped directories:");
+
+        for directory in &self.skipped_directories {
+            self.log
+                .line(&format!("    {}", directory.to_string_lossy()));
+        }
+
+        self.log.line("");
+    }
+
+    fn print_excluded_files(&mut self) {
+        if self.excluded_files.is_empty() {
+            return;
+        }
+
+        self.log
+            .line(&format!("Excluded files: {}\n", self.excluded_files.len()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{configuration, git, malformed_source, TempDirectory};
+    use std::fs;
+    use std::path::Path;
+
+    fn export(source: &Path, options: &[&str]) -> Result<usize> {
+        let destination = source.with_file_name("destination");
+        let source_arg = source.to_string_lossy();
+        let destination_arg = destination.to_string_lossy();
+        let args = ["-s", &source_arg, "-d", &destination_arg, "-q"];
+        let configuration = configuration(&[&args, options].concat());
+        let mut file_checker = FileChecker::new(destination.clone(), &configuration);
+        let mut exportbranch = ExportBranch::build(
+            source.to_path_buf(),
+            destination,
+            &configuration,
+            &mut file_checker,
+        )?;
+
+        exportbranch.perform_exporting()
+    }
+
+    #[test]
+    fn invalid_configuration_files_stop_the_export() {
+        let directory = TempDirectory::new();
+        let source = malformed_source(&directory);
+
+        match export(&source, &["-f", "*.prg;*.bak", "--invalid-exb", "error"]) {
+            Err(Error::Multiple(problems)) => assert_eq!(problems.len(), 3),
+            _ => panic!("expected the export to stop"),
+        }
+        assert!(!directory.join("destination/a.prg").exists());
+    }
+
+    #[test]
+    fn invalid_entries_are_skipped_with_warn() {
+        let directory = TempDirectory::new();
+        let source = malformed_source(&directory);
+        let destination = directory.join("destination");
+
+        assert_eq!(
+            export(&source, &["-f", "*.prg;*.bak", "--invalid-exb", "warn"]).ok(),
+            Some(0)
+        );
+        assert_eq!(fs::read(destination.join("a.prg")).unwrap(), b"a\n");
+        assert!(!destination.join("b.bak").exists());
+        assert!(destination.join("sub").join("c.prg").exists());
+    }
+
+    #[test]
+    fn files_unchanged_since_the_revision_are_restored() {
+        let directory = TempDirectory::new();
+        let source = directory.join("source");
+        let exported = directory.join("destination").join("a.prg");
+        directory.write("source/a.prg", "a\r\n");
+        git(&source, &["init", "-q"]);
+        git(&source, &["add", "-A"]);
+        git(&source, &["commit", "-q", "-m", "first"]);
+
+        assert_eq!(export(&source, &["--since", "HEAD"]).ok(), Some(0));
+        assert_eq!(fs::read(&exported).unwrap(), b"a\n");
+
+        fs::write(&exported, "stale").unwrap();
+        assert_eq!(export(&source, &["--since", "HEAD"]).ok(), Some(0));
+        assert_eq!(fs::read(&exported).unwrap(), b"stale");
+
+        assert_eq!(
+            export(&source, &["--since", "HEAD", "--reload"]).ok(),
+            Some(0)
+        );
+        assert_eq!(fs::read(&exported).unwrap(), b"a\n");
+
+        fs::remove_file(&exported).unwrap();
+        assert_eq!(export(&source, &["--since", "HEAD"]).ok(), Some(0));
+        assert_eq!(fs::read(&exported).unwrap(), b"a\n");
     }
 }
@@ -1,93 +1,539 @@
-use regex::Regex;
+use crate::configuration::{Configuration, ExbPolicy};
+use crate::convert_file::Converter;
+use crate::convertions::read_conversion_table;
+use crate::error::{Error, Result};
+use crate::file_pattern::{
+    build_exclusion, build_exclusions, build_patterns, Exclusion, FilePattern,
+};
+use crate::ignore_files::{check_ignore_files, is_ignore_file, repository_ignores};
 use std::fs;
-use std::io::Error;
 use std::io::ErrorKind;
-use std::io::Read;
-use std::io::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-pub fn check_configuration_file(
-    directory: &PathBuf,
-    file_filters: Vec<Regex>,
-    only_copy_files: Vec<Regex>,
-) -> (Vec<Regex>, Vec<Regex>) {
-    let config_only_copy = read_config_file(directory, "extecoesapenascopiar.exb");
-    let config_do_not_convert = read_config_file(directory, "naoconverteacentos.exb");
+const CONFIG_ONLY_COPY: &str = "extecoesapenascopiar.exb";
+const CONFIG_DO_NOT_CONVERT: &str = "naoconverteacentos.exb";
+const CONFIG_UNIFIED: &str = "exportbranch.exb";
 
-    (
-        check_filters(&config_only_copy, file_filters),
-        check_only_copy(config_only_copy, config_do_not_convert, only_copy_files),
-    )
+#[derive(Clone)]
+pub struct Filters {
+    pub file_filters: Vec<FilePattern>,
+    pub only_copy_files: Vec<FilePattern>,
+    pub exclusions: Vec<Exclusion>,
+    pub disregarded_directories: Vec<PathBuf>,
+    pub ignored: Option<Vec<Exclusion>>,
+    // Where `convert = false` turned conversion off, if it did.
+    pub do_not_convert: Option<String>,
+    pub conversion: Option<Conversion>,
+    pub configuration_files: Vec<PathBuf>,
 }
 
-pub fn checked_to_regex(checked: Vec<String>) -> Vec<Regex> {
-    let mut regex: Vec<Regex> = vec![];
+// A conversion table set by an exportbranch.exb, replacing the configured one
+// for its directory and the directories below it.
+#[derive(Clone)]
+pub struct Conversion {
+    pub table: PathBuf,
+    pub converter: Arc<Converter>,
+    pub origin: String,
+}
+
+enum Operation {
+    Add,
+    Remove,
+    Set,
+}
+
+struct Directive {
+    key: String,
+    operation: Operation,
+    values: Vec<String>,
+}
+
+impl Filters {
+    // The filters of the source before any of its configuration files is read.
+    pub fn build(
+        configuration: &Configuration,
+        source: &Path,
+        problems: &mut Vec<Error>,
+    ) -> Result<Filters> {
+        let ignore_case = configuration.ignore_case();
 
-    for file in checked {
-        let file = file.replace(".", "\\.").replace("*", ".*");
-        regex.push(Regex::new(&file).unwrap());
+        Ok(Filters {
+            file_filters: build_patterns(
+                configuration.file_filters(),
+                source,
+                ignore_case,
+                &configuration.origin("file_filters"),
+            )?,
+            only_copy_files: build_patterns(
+                configuration.only_copy_files(),
+                source,
+                ignore_case,
+                &configuration.origin("only_copy_files"),
+            )?,
+            exclusions: build_exclusions(
+                configuration.exclusions(),
+                source,
+                ignore_case,
+                &configuration.origin("exclude"),
+            )?,
+            disregarded_directories: configuration
+                .disregarded_directories()
+                .iter()
+                .map(PathBuf::from)
+                .collect(),
+            ignored: match configuration.ignore_files() {
+                true => Some(repository_ignores(source, ignore_case, problems)),
+                false => None,
+            },
+            do_not_convert: None,
+            conversion: None,
+            configuration_files: Vec::new(),
+        })
     }
+}
+
+pub fn check_configuration_files(
+    directory: &Path,
+    filters: &Filters,
+    ignore_case: bool,
+    policy: ExbPolicy,
+) -> Result<Filters> {
+    let mut problems = Vec::new();
+    let checked = read_configuration_files(directory, filters, ignore_case, &mut problems);
+
+    report_problems(problems, policy)?;
 
-    regex
+    Ok(checked)
 }
 
-fn check_filters(
-    config_only_copy: &Result<Vec<String>>,
-    only_copy_files: Vec<Regex>,
-) -> Vec<Regex> {
-    match config_only_copy {
-        Ok(checked) => checked_to_regex(checked.clone()),
-        Err(_) => only_copy_files,
+// Invalid configuration files stop the export unless the policy is to warn.
+pub fn report_problems(mut problems: Vec<Error>, policy: ExbPolicy) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    match policy {
+        ExbPolicy::Error if problems.len() == 1 => Err(problems.remove(0)),
+        ExbPolicy::Error => Err(Error::Multiple(problems)),
+        ExbPolicy::Warn => {
+            for problem in problems {
+                eprintln!("Warning: {}", problem);
+            }
+            Ok(())
+        }
     }
 }
 
-fn check_only_copy(
-    config_only_copy: Result<Vec<String>>,
-    config_do_not_convert: Result<Vec<String>>,
-    only_copy_files: Vec<Regex>,
-) -> Vec<Regex> {
-    if config_only_copy.is_err() && config_do_not_convert.is_err() {
-        return only_copy_files;
+// Applies the configuration files of the directory to the inherited filters.
+// Invalid entries are left out and recorded in problems.
+pub fn read_configuration_files(
+    directory: &Path,
+    filters: &Filters,
+    ignore_case: bool,
+    problems: &mut Vec<Error>,
+) -> Filters {
+    let mut checked = filters.clone();
+
+    check_configuration_file(directory, &mut checked, ignore_case, problems);
+
+    if let Some(ignored) = &mut checked.ignored {
+        check_ignore_files(directory, ignored, ignore_case, problems);
     }
 
-    let mut checked: Vec<String> = vec![];
+    checked.configuration_files.extend(
+        [CONFIG_ONLY_COPY, CONFIG_DO_NOT_CONVERT]
+            .iter()
+            .map(|config_file| directory.join(config_file))
+            .filter(|config_file| config_file.exists()),
+    );
+
+    check_unified_file(directory, &mut checked, ignore_case, problems);
+
+    checked
+}
+
+// exportbranch.exb is read after the legacy files, so its directives apply to
+// what the directory inherited and to what those files declared.
+fn check_unified_file(
+    directory: &Path,
+    filters: &mut Filters,
+    ignore_case: bool,
+    problems: &mut Vec<Error>,
+) {
+    let file = directory.join(CONFIG_UNIFIED);
+
+    let contents = match read_file(&file, problems) {
+        Some(contents) => contents,
+        None => return,
+    };
+
+    filters.configuration_files.push(file.clone());
+
+    for (index, line) in contents.lines().enumerate() {
+        let origin = format!("{}:{}", file.to_string_lossy(), index + 1);
+
+        let applied = match parse_directive(line) {
+            Ok(Some(directive)) => {
+                apply_directive(directory, filters, &directive, &origin, ignore_case)
+            }
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
 
-    if let Ok(mut files) = config_only_copy {
-        checked.append(&mut files);
+        if let Err(err) = applied {
+            problems.push(Error::at(&file, Some(index + 1), err));
+        }
     }
+}
+
+// Each line is `key = value`, `key += value` or `key -= value`, values being
+// separated by `;`; blank lines and lines starting with `#` are skipped.
+fn parse_directive(line: &str) -> Result<Option<Directive>> {
+    let line = line.trim();
 
-    if let Ok(mut files) = config_do_not_convert {
-        checked.append(&mut files);
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
     }
 
-    checked_to_regex(checked)
+    let (key, operation, value) = match line.split_once('=') {
+        Some((key, value)) => match key.strip_suffix('+') {
+            Some(key) => (key, Operation::Add, value),
+            None => match key.strip_suffix('-') {
+                Some(key) => (key, Operation::Remove, value),
+                None => (key, Operation::Set, value),
+            },
+        },
+        None => {
+            return Err(Error::Config(format!(
+                "expected \"key = value\", \"key += value\" or \"key -= value\", found \"{}\"",
+                line
+            )))
+        }
+    };
+
+    Ok(Some(Directive {
+        key: key.trim().to_string(),
+        operation,
+        values: value
+            .split(';')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect(),
+    }))
+}
+
+fn apply_directive(
+    directory: &Path,
+    filters: &mut Filters,
+    directive: &Directive,
+    origin: &str,
+    ignore_case: bool,
+) -> Result<()> {
+    match directive.key.as_str() {
+        "filter" => update(
+            &mut filters.file_filters,
+            directive,
+            |pattern| FilePattern::build(pattern, directory, ignore_case, origin),
+            |pattern, text| pattern.text() == text,
+        ),
+        "only-copy" => update(
+            &mut filters.only_copy_files,
+            directive,
+            |pattern| FilePattern::build(pattern, directory, ignore_case, origin),
+            |pattern, text| pattern.text() == text,
+        ),
+        "exclude" => update(
+            &mut filters.exclusions,
+            directive,
+            |pattern| build_exclusion(pattern, directory, ignore_case, origin),
+            |exclusion, text| exclusion.text() == text,
+        ),
+        "disregard" => update(
+            &mut filters.disregarded_directories,
+            directive,
+            |disregarded| Ok(directory.join(disregarded)),
+            |disregarded, text| *disregarded == directory.join(text),
+        ),
+        "convert" => {
+            filters.do_not_convert = match single_value(directive)? {
+                "true" => None,
+                "false" => Some(origin.to_string()),
+                value => {
+                    return Err(Error::Config(format!(
+                        "Invalid value \"{}\" for \"convert\": expected true or false",
+                        value
+                    )))
+                }
+            };
+            Ok(())
+        }
+        "conversion-table" => {
+            filters.conversion = match single_value(directive)? {
+                "" => None,
+                table => {
+                    let table = directory.join(table);
+
+                    Some(Conversion {
+                        converter: Arc::new(Converter::build(read_conversion_table(&table)?)),
+                        table,
+                        origin: origin.to_string(),
+                    })
+                }
+            };
+            Ok(())
+        }
+        key => Err(Error::Config(format!("Unknown key \"{}\"", key))),
+    }
 }
 
-fn read_config_file(directory: &PathBuf, config_file: &str) -> Result<Vec<String>> {
-    let file_name: PathBuf = directory.join(config_file);
+// Every value is built before the list changes, so an invalid directive
+// leaves what the directory inherited untouched.
+fn update<T>(
+    list: &mut Vec<T>,
+    directive: &Directive,
+    build: impl Fn(&str) -> Result<T>,
+    matches: impl Fn(&T, &str) -> bool,
+) -> Result<()> {
+    if let Operation::Remove = directive.operation {
+        list.retain(|x| !directive.values.iter().any(|value| matches(x, value)));
+        return Ok(());
+    }
+
+    let mut built = directive
+        .values
+        .iter()
+        .map(|value| build(value))
+        .collect::<Result<Vec<T>>>()?;
 
-    if !file_name.exists() {
-        return Err(Error::new(
-            ErrorKind::NotFound,
-            format!("File not found: {:?}", file_name),
-        ));
+    if let Operation::Set = directive.operation {
+        list.clear();
     }
 
-    let mut file: fs::File = fs::File::open(file_name)?;
-    let mut config_file_buffer: String = String::new();
+    list.append(&mut built);
 
-    file.read_to_string(&mut config_file_buffer)?;
+    Ok(())
+}
 
-    let config_file_vec: Vec<&str> = config_file_buffer.split(";").collect::<Vec<&str>>();
-    let mut config: Vec<String> = vec![];
+fn single_value(directive: &Directive) -> Result<&str> {
+    if !matches!(directive.operation, Operation::Set) {
+        return Err(Error::Config(format!(
+            "\"{}\" only supports =",
+            directive.key
+        )));
+    }
 
-    for file in config_file_vec {
-        let file_filter = file.replace(char::from(10), "").replace(char::from(13), "");
+    match directive.values.as_slice() {
+        [] => Ok(""),
+        [value] => Ok(value),
+        _ => Err(Error::Config(format!(
+            "\"{}\" takes a single value",
+            directive.key
+        ))),
+    }
+}
+
+fn check_configuration_file(
+    directory: &Path,
+    filters: &mut Filters,
+    ignore_case: bool,
+    problems: &mut Vec<Error>,
+) {
+    let config_only_copy = checked_to_patterns(directory, CONFIG_ONLY_COPY, ignore_case, problems);
+    let config_do_not_convert =
+        checked_to_patterns(directory, CONFIG_DO_NOT_CONVERT, ignore_case, problems);
+
+    check_filters(&config_only_copy, filters);
+    check_only_copy(config_only_copy, config_do_not_convert, filters);
+}
+
+pub fn is_configuration_file(file: &Path) -> bool {
+    if is_ignore_file(file) {
+        return true;
+    }
+
+    match file.file_name() {
+        Some(file_name) => [CONFIG_ONLY_COPY, CONFIG_DO_NOT_CONVERT, CONFIG_UNIFIED]
+            .iter()
+            .any(|config_file| file_name == *config_file),
+        None => false,
+    }
+}
+
+fn checked_to_patterns(
+    directory: &Path,
+    config_file: &str,
+    ignore_case: bool,
+    problems: &mut Vec<Error>,
+) -> Option<Vec<FilePattern>> {
+    let file = directory.join(config_file);
+    let checked = read_config_file(&file, problems)?;
+    let mut patterns = Vec::new();
+
+    for (line, pattern) in checked {
+        let origin = format!("{}:{}", file.to_string_lossy(), line);
+
+        match FilePattern::build(&pattern, directory, ignore_case, &origin) {
+            Ok(pattern) => patterns.push(pattern),
+            Err(err) => problems.push(Error::at(&file, Some(line), err)),
+        }
+    }
+
+    Some(patterns)
+}
+
+fn check_filters(config_only_copy: &Option<Vec<FilePattern>>, filters: &mut Filters) {
+    if let Some(checked) = config_only_copy {
+        filters.file_filters = checked.clone();
+    }
+}
+
+fn check_only_copy(
+    config_only_copy: Option<Vec<FilePattern>>,
+    config_do_not_convert: Option<Vec<FilePattern>>,
+    filters: &mut Filters,
+) {
+    if config_only_copy.is_none() && config_do_not_convert.is_none() {
+        return;
+    }
+
+    filters.only_copy_files = config_only_copy
+        .into_iter()
+        .chain(config_do_not_convert)
+        .flatten()
+        .collect();
+}
+
+// The legacy files hold patterns separated by `;`, line breaks being dropped;
+// each entry is returned with the line it starts on.
+fn read_config_file(file: &Path, problems: &mut Vec<Error>) -> Option<Vec<(usize, String)>> {
+    let contents = read_file(file, problems)?;
+    let mut config: Vec<(usize, String)> = vec![];
+    let mut line = 1;
+
+    for entry in contents.split(';') {
+        let leading = entry.len() - entry.trim_start_matches(['\n', '\r']).len();
+        let entry_line = line + entry[..leading].matches('\n').count();
+        let file_filter = entry.replace([char::from(10), char::from(13)], "");
+
+        line += entry.matches('\n').count();
 
         if !file_filter.is_empty() {
-            config.push(file_filter);
+            config.push((entry_line, file_filter));
+        }
+    }
+
+    Some(config)
+}
+
+// A missing file is not a problem: most directories have no configuration.
+pub fn read_file(file: &Path, problems: &mut Vec<Error>) -> Option<String> {
+    match fs::read_to_string(file) {
+        Ok(contents) => Some(contents),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => {
+            problems.push(Error::io(file, err));
+            None
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{configuration, TempDirectory};
+
+    fn texts(patterns: &[FilePattern]) -> Vec<&str> {
+        patterns.iter().map(FilePattern::text).collect()
+    }
+
+    fn exclusion_texts(exclusions: &[Exclusion]) -> Vec<String> {
+        exclusions.iter().map(Exclusion::text).collect()
+    }
+
+    fn root_filters(root: &Path) -> Filters {
+        let configuration = configuration(&[
+            "-s",
+            &root.to_string_lossy(),
+            "-d",
+            "destination",
+            "-f",
+            "*.prg",
+            "-c",
+            "*.so",
+        ]);
+        let mut problems = Vec::new();
+        let filters = Filters::build(&configuration, root, &mut problems)
+            .ok()
+            .unwrap();
+
+        assert!(problems.is_empty());
+        filters
+    }
+
+    #[test]
+    fn directives_add_remove_and_reset_inherited_values() {
+        let directory = TempDirectory::new();
+        let root = directory.path();
+        directory.write(
+            CONFIG_UNIFIED,
+            "filter += *.txt\nexclude += tests/; !tests/keep.prg\nonly-copy += *.h\n",
+        );
+        directory.write(Path::new("sub").join(CONFIG_DO_NOT_CONVERT), "*.c");
+        directory.write(
+            Path::new("sub").join(CONFIG_UNIFIED),
+            "# reset\nfilter -= *.txt\nonly-copy += *.a\nexclude =\nconvert = false\n",
+        );
+        directory.write(
+            Path::new("sub/deep").join(CONFIG_UNIFIED),
+            "filter = *.c; *.h\nconvert = true\ndisregard += bin\n",
+        );
+
+        let mut problems = Vec::new();
+        let filters = read_configuration_files(root, &root_filters(root), false, &mut problems);
+        let sub = read_configuration_files(&root.join("sub"), &filters, false, &mut problems);
+        let deep = read_configuration_files(&root.join("sub/deep"), &sub, false, &mut problems);
+
+        assert!(problems.is_empty());
+        assert_eq!(texts(&filters.file_filters), ["*.prg", "*.txt"]);
+        assert_eq!(texts(&filters.only_copy_files), ["*.so", "*.h"]);
+        assert_eq!(
+            exclusion_texts(&filters.exclusions),
+            ["tests/", "!tests/keep.prg"]
+        );
+
+        // The legacy file replaces the only copy patterns before the directives.
+        assert_eq!(texts(&sub.file_filters), ["*.prg"]);
+        assert_eq!(texts(&sub.only_copy_files), ["*.c", "*.a"]);
+        assert!(sub.exclusions.is_empty());
+        assert!(sub.do_not_convert.is_some());
 
-    Ok(config)
+        assert_eq!(texts(&deep.file_filters), ["*.c", "*.h"]);
+        assert_eq!(texts(&deep.only_copy_files), ["*.c", "*.a"]);
+        assert!(deep.do_not_convert.is_none());
+        assert_eq!(
+            deep.disregarded_directories.last(),
+            Some(&root.join("sub/deep/bin"))
+        );
+        assert_eq!(deep.configuration_files.len(), 4);
+    }
+
+    #[test]
+    fn invalid_directives_leave_the_inherited_values() {
+        let directory = TempDirectory::new();
+        let root = directory.path();
+        directory.write(
+            CONFIG_UNIFIED,
+            "filter += *.txt; [bad\nonly-copy -= *.so\nconvert += false\nfilter\ncolour = red\n",
+        );
+
+        let mut problems = Vec::new();
+        let filters = read_configuration_files(root, &root_filters(root), false, &mut problems);
+
+        assert_eq!(problems.len(), 4);
+        assert_eq!(texts(&filters.file_filters), ["*.prg"]);
+        assert!(filters.only_copy_files.is_empty());
+        assert!(filters.do_not_convert.is_none());
+    }
 }
@@ -0,0 +1,406 @@
+use crate::convert_file::{convert_file, copy_file, Converter, Transfer};
+use crate::error::Result;
+use crate::file_checker::{FileCheckMode, FileMetadata, FileStatus, UpdateReason};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Jobs queued per worker; the walk waits when the queue is full.
+const QUEUE_PER_THREAD: usize = 4;
+
+pub struct ExportJob {
+    pub source_file: PathBuf,
+    pub destination_file: PathBuf,
+    pub only_copy: bool,
+    pub force: Option<UpdateReason>,
+    pub stored: Option<FileMetadata>,
+    // Set when an exportbranch.exb replaced the conversion table.
+    pub converter: Option<Arc<Converter>>,
+}
+
+pub enum ExportOutcome {
+    UpToDate,
+    DryRun(UpdateReason),
+    Exported {
+        source_metadata: String,
+        output: Option<String>,
+        transfer: Transfer,
+        reason: UpdateReason,
+    },
+    Failed(io::Error),
+}
+
+// Runs the jobs on worker threads while the walk is still submitting them.
+// Outcomes are handed back in the order the jobs were submitted.
+pub struct Pipeline {
+    sender: Option<SyncSender<(usize, ExportJob)>>,
+    results: Receiver<(usize, ExportJob, ExportOutcome)>,
+    workers: Vec<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+    submitted: usize,
+    next_merge: usize,
+    finished: BTreeMap<usize, (ExportJob, ExportOutcome)>,
+}
+
+impl Pipeline {
+    pub fn start(
+        threads: usize,
+        mode: FileCheckMode,
+        converter: Arc<Converter>,
+        dry_run: bool,
+        report: bool,
+    ) -> Pipeline {
+        let threads = threads.max(1);
+        let (sender, receiver) =
+            mpsc::sync_channel::<(usize, ExportJob)>(threads * QUEUE_PER_THREAD);
+        let (result_sender, results) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..threads)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let result_sender = result_sender.clone();
+                let converter = Arc::clone(&converter);
+                let stop = Arc::clone(&stop);
+
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let next = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => break,
+                        };
+                        let (index, job) = match next {
+                            Ok(next) => next,
+                            Err(_) => break,
+                        };
+                        let outcome = run_job(&job, mode, &converter, dry_run, report);
+
+                        if result_sender.send((index, job, outcome)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Pipeline {
+            sender: Some(sender),
+            results,
+            workers,
+            stop,
+            submitted: 0,
+            next_merge: 0,
+            finished: BTreeMap::new(),
+        }
+    }
+
+    pub fn submit(&mut self, job: ExportJob) {
+        if let Some(sender) = &self.sender {
+            // Only fails when every worker is gone, which finish() reports.
+            let _ = sender.send((self.submitted, job));
+        }
+        self.submitted += 1;
+    }
+
+    pub fn submitted(&self) -> usize {
+        self.submitted
+    }
+
+    // The jobs finished so far that come next in submission order.
+    pub fn ready(&mut self) -> Vec<(ExportJob, ExportOutcome)> {
+        while let Ok((index, job, outcome)) = self.results.try_recv() {
+            self.finished.insert(index, (job, outcome));
+        }
+
+        self.take_ready()
+    }
+
+    // Waits for the rest of the jobs once the walk is over.
+    pub fn finish(&mut self) -> Vec<(ExportJob, ExportOutcome)> {
+        self.sender = None;
+
+        while self.next_merge + self.finished.len() < self.submitted {
+            match self.results.recv() {
+                Ok((index, job, outcome)) => {
+                    self.finished.insert(index, (job, outcome));
+                }
+                Err(_) => break,
+            }
+        }
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+
+        let mut ready = self.take_ready();
+        ready.extend(std::mem::take(&mut self.finished).into_values());
+        ready
+    }
+
+    fn take_ready(&mut self) -> Vec<(ExportJob, ExportOutcome)> {
+        let mut ready = Vec::new();
+
+        while let Some(finished) = self.finished.remove(&self.next_merge) {
+            ready.push(finished);
+            self.next_merge += 1;
+        }
+
+        ready
+    }
+}
+
+// Stops the workers when the export ends early with an error.
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.sender = None;
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Runs work on the jobs over the threads and merges the results in the order
+// of the jobs.
+pub fn run_parallel<T: Send>(
+    jobs: &[ExportJob],
+    threads: usize,
+    work: impl Fn(&ExportJob) -> T + Sync,
+    mut merge: impl FnMut(&ExportJob, T) -> Result<()>,
+) -> Result<()> {
+    let next_job = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let sender = sender.clone();
+            let next_job = &next_job;
+            let stop = &stop;
+            let work = &work;
+
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next_job.fetch_add(1, Ordering::Relaxed);
+
+                    match jobs.get(index) {
+                        Some(job) => {
+                            if sender.send((index, work(job))).is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    }
+                }
+            });
+        }
+
+        drop(sender);
+
+        let mut finished = BTreeMap::new();
+        let mut next_merge = 0;
+
+        for (index, outcome) in receiver {
+            finished.insert(index, outcome);
+
+            while let Some(outcome) = finished.remove(&next_merge) {
+                if let Err(err) = merge(&jobs[next_merge], outcome) {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+                next_merge += 1;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn run_job(
+    job: &ExportJob,
+    mode: FileCheckMode,
+    converter: &Converter,
+    dry_run: bool,
+    report: bool,
+) -> ExportOutcome {
+    let status = match job.force {
+        Some(reason) => mode.force_update(&job.source_file, reason),
+        None => mode.check(&job.source_file, &job.destination_file, job.stored.as_ref()),
+    };
+
+    let (source_metadata, reason) = match status {
+        FileStatus::UpToDate => return ExportOutcome::UpToDate,
+        FileStatus::Modified(source_metadata, reason) => (source_metadata, reason),
+    };
+
+    if dry_run {
+        return ExportOutcome::DryRun(reason);
+    }
+
+    let exported = if job.only_copy {
+        copy_file(&job.source_file, &job.destination_file)
+    } else {
+        convert_file(
+            job.converter.as_deref().unwrap_or(converter),
+            &job.source_file,
+            &job.destination_file,
+            report,
+        )
+    };
+
+    match exported {
+        Err(err) => ExportOutcome::Failed(err),
+        Ok(transfer) => ExportOutcome::Exported {
+            source_metadata,
+            output: mode.output(&job.destination_file),
+            transfer,
+            reason,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convertions::default_conversion_table;
+    use crate::file_checker::FileChecker;
+    use crate::test_fixtures::{configuration, TempDirectory};
+    use std::fs;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    fn start(directory: &TempDirectory, threads: usize, converter: &Arc<Converter>) -> Pipeline {
+        let destination = directory.join("destination");
+        let configuration = configuration(&["-s", ".", "-d", &destination.to_string_lossy()]);
+        let mode = FileChecker::new(destination, &configuration).mode();
+
+        Pipeline::start(threads, mode, Arc::clone(converter), false, false)
+    }
+
+    fn job(directory: &TempDirectory, name: &str) -> ExportJob {
+        ExportJob {
+            source_file: directory.join("source").join(name),
+            destination_file: directory.join("destination").join(name),
+            only_copy: false,
+            force: None,
+            stored: None,
+            converter: None,
+        }
+    }
+
+    fn converter() -> Arc<Converter> {
+        Arc::new(Converter::build(default_conversion_table()))
+    }
+
+    // The files are of very different sizes, so the workers finish them out
+    // of order.
+    fn exported_order(threads: usize) -> Vec<PathBuf> {
+        let directory = TempDirectory::new();
+        fs::create_dir_all(directory.join("destination")).unwrap();
+        let mut pipeline = start(&directory, threads, &converter());
+        let mut merged = Vec::new();
+
+        for index in 0..40 {
+            let name = format!("{:02}.prg", index);
+            let size = if index % 3 == 0 { 20_000 } else { 10 };
+            directory.write(format!("source/{}", name), "a\r\n".repeat(size));
+
+            pipeline.submit(job(&directory, &name));
+            merged.extend(pipeline.ready());
+        }
+        merged.extend(pipeline.finish());
+
+        merged
+            .into_iter()
+            .map(|(job, outcome)| {
+                assert!(matches!(outcome, ExportOutcome::Exported { .. }));
+                job.source_file
+                    .strip_prefix(directory.path())
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn outcomes_are_merged_in_submission_order() {
+        let expected: Vec<PathBuf> = (0..40)
+            .map(|index| PathBuf::from(format!("source/{:02}.prg", index)))
+            .collect();
+
+        assert_eq!(exported_order(1), expected);
+        assert_eq!(exported_order(4), expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_walk_waits_when_the_queue_is_full() {
+        let directory = TempDirectory::new();
+        fs::create_dir_all(directory.join("source")).unwrap();
+        fs::create_dir_all(directory.join("destination")).unwrap();
+        let fifo = directory.join("source").join("fifo.prg");
+        assert!(Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap()
+            .success());
+
+        let mut pipeline = start(&directory, 1, &converter());
+        let submitted = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                // The worker blocks reading the fifo, the next jobs fill the
+                // queue and the one after them waits for room.
+                pipeline.submit(job(&directory, "fifo.prg"));
+                submitted.fetch_add(1, Ordering::SeqCst);
+
+                for index in 0..QUEUE_PER_THREAD + 1 {
+                    let name = format!("{}.prg", index);
+                    directory.write(format!("source/{}", name), "a");
+                    pipeline.submit(job(&directory, &name));
+                    submitted.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+
+            let waiting = Instant::now();
+            while submitted.load(Ordering::SeqCst) < QUEUE_PER_THREAD + 1 {
+                assert!(waiting.elapsed() < Duration::from_secs(10));
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(100));
+            assert_eq!(submitted.load(Ordering::SeqCst), QUEUE_PER_THREAD + 1);
+
+            fs::write(&fifo, "a").unwrap();
+        });
+
+        let finished = pipeline.finish();
+        assert_eq!(finished.len(), QUEUE_PER_THREAD + 2);
+    }
+
+    #[test]
+    fn dropping_the_pipeline_stops_the_workers() {
+        let directory = TempDirectory::new();
+        fs::create_dir_all(directory.join("destination")).unwrap();
+        let converter = converter();
+        let mut pipeline = start(&directory, 4, &converter);
+
+        for index in 0..20 {
+            let name = format!("{}.prg", index);
+            directory.write(format!("source/{}", name), "a\r\n".repeat(10_000));
+            pipeline.submit(job(&directory, &name));
+        }
+        assert!(Arc::strong_count(&converter) > 1);
+
+        drop(pipeline);
+
+        // Every worker held a reference to the converter until it ended.
+        assert_eq!(Arc::strong_count(&converter), 1);
+    }
+}
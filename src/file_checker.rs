@@ -1,120 +1,576 @@
+use crate::configuration::Configuration;
+use crate::error::{Error, Result};
+use md5::{Digest, Md5};
+use regex::Regex;
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
-use std::io::Result;
-use std::io::{Read, Write};
+use std::io;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const EXPORT_BRANCH_FILES_METADATA: &str = "export_branch_files_metadata.txt";
+const EXPORT_BRANCH_FILES_METADATA_TEMP: &str = "export_branch_files_metadata.txt.tmp";
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+const CHECKPOINT_FILES: usize = 500;
+const METADATA_FORMAT_VERSION: &str = "3";
+const METADATA_HEADER: &str = "#exportbranch-metadata";
+const WINDOWS_TO_UNIX_EPOCH_INTERVALS: u64 = 116_444_736_000_000_000;
 
 pub enum FileStatus {
     UpToDate,
-    Modified(SystemTime),
+    Modified(String, UpdateReason),
+}
+
+#[derive(Clone, Copy)]
+pub enum UpdateReason {
+    Reload,
+    MissingDestination,
+    New,
+    SourceChanged,
+    DestinationChanged,
+    GitChanged,
+}
+
+#[derive(Clone)]
+pub struct FileMetadata {
+    source: String,
+    output: Option<String>,
+    destination: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub struct FileCheckMode {
+    md5: bool,
+    md5_output: bool,
 }
 
 pub struct FileChecker {
     directory: PathBuf,
-    files: HashMap<String, String>,
+    files: HashMap<String, FileMetadata>,
+    mode: FileCheckMode,
+    header: Vec<(String, String)>,
+    pending: usize,
+    last_saved: Instant,
 }
 
 impl FileChecker {
-    pub fn new(directory: PathBuf) -> FileChecker {
-        match FileChecker::read_file(&directory) {
-            Ok(contents) => FileChecker::build(directory, contents),
+    pub fn new(directory: PathBuf, configuration: &Configuration) -> FileChecker {
+        let header = FileChecker::header(configuration);
+        let mut file_checker = match FileChecker::read_file(&directory) {
+            Ok(contents) => FileChecker::build(directory, contents, &header),
             _ => FileChecker::default(directory),
+        };
+
+        file_checker.mode = FileCheckMode {
+            md5: configuration.md5() || configuration.md5_output(),
+            md5_output: configuration.md5_output(),
+        };
+        file_checker.header = header;
+        file_checker
+    }
+
+    pub fn is_metadata_file(file: &Path) -> bool {
+        file.file_name().is_some_and(|name| {
+            name == EXPORT_BRANCH_FILES_METADATA || name == EXPORT_BRANCH_FILES_METADATA_TEMP
+        })
+    }
+
+    pub fn mode(&self) -> FileCheckMode {
+        self.mode
+    }
+
+    pub fn stored(&self, file: &Path) -> Result<Option<FileMetadata>> {
+        Ok(self.files.get(key(file)?).cloned())
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        let temp_file_name = self.directory.join(EXPORT_BRANCH_FILES_METADATA_TEMP);
+        let mut contents = format!("{} {}\n", METADATA_HEADER, METADATA_FORMAT_VERSION);
+
+        for (key, value) in &self.header {
+            contents.push_str(&format!("#{} {}\n", key, escape(value)));
         }
+
+        let mut file_names: Vec<&String> = self.files.keys().collect();
+        file_names.sort();
+
+        for file_name in file_names {
+            let file_metadata = &self.files[file_name];
+
+            contents.push_str(&format!(
+                "{};{};{};{}\n",
+                escape(file_name),
+                escape(&file_metadata.source),
+                escape(file_metadata.output.as_deref().unwrap_or("")),
+                escape(file_metadata.destination.as_deref().unwrap_or(""))
+            ));
+        }
+
+        let file_name = self.directory.join(EXPORT_BRANCH_FILES_METADATA);
+
+        write_file(&temp_file_name, &contents).map_err(|err| Error::io(&temp_file_name, err))?;
+        fs::rename(&temp_file_name, &file_name).map_err(|err| Error::io(&file_name, err))?;
+
+        self.pending = 0;
+        self.last_saved = Instant::now();
+        Ok(())
     }
 
-    pub fn check(&mut self, file: &PathBuf) -> FileStatus {
-        match FileChecker::get_modified(file) {
-            Ok(system_time) => match self.files.get(file.to_str().unwrap()) {
-                Some(file_modified) => {
-                    if *file_modified == format!("{:?}", system_time) {
-                        FileStatus::UpToDate
-                    } else {
-                        FileStatus::Modified(system_time)
-                    }
-                }
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if self.pending >= CHECKPOINT_FILES
+            || (self.pending > 0 && self.last_saved.elapsed() >= CHECKPOINT_INTERVAL)
+        {
+            return self.save();
+        }
 
-                _ => FileStatus::Modified(system_time),
+        Ok(())
+    }
+
+    pub fn add_file(
+        &mut self,
+        file: &Path,
+        source_metadata: String,
+        output: Option<String>,
+        destination_file: &Path,
+    ) -> Result<()> {
+        let destination = key(
+            destination_file
+                .strip_prefix(&self.directory)
+                .unwrap_or(destination_file),
+        )?;
+
+        self.files.insert(
+            key(file)?.to_string(),
+            FileMetadata {
+                source: source_metadata,
+                output,
+                destination: Some(destination.to_string()),
             },
+        );
+        self.pending += 1;
+        Ok(())
+    }
 
-            _ => FileStatus::Modified(SystemTime::now()),
+    pub fn files_under(&self, directory: &Path) -> Vec<(PathBuf, Option<PathBuf>)> {
+        let mut files: Vec<(PathBuf, Option<PathBuf>)> = self
+            .files
+            .iter()
+            .map(|(file, file_metadata)| {
+                (
+                    PathBuf::from(file),
+                    file_metadata
+                        .destination
+                        .as_ref()
+                        .map(|destination| self.directory.join(destination))
+                        .filter(|destination| destination.starts_with(&self.directory)),
+                )
+            })
+            .filter(|(file, _)| file.starts_with(directory))
+            .collect();
+
+        files.sort();
+        files
+    }
+
+    pub fn remove_file(&mut self, file: &Path) -> Result<()> {
+        if self.files.remove(key(file)?).is_some() {
+            self.pending += 1;
+        }
+        Ok(())
+    }
+}
+
+impl UpdateReason {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            UpdateReason::Reload => "reload",
+            UpdateReason::MissingDestination => "destination missing",
+            UpdateReason::New => "new file",
+            UpdateReason::SourceChanged => "source changed",
+            UpdateReason::DestinationChanged => "destination changed",
+            UpdateReason::GitChanged => "changed since revision",
         }
     }
+}
+
+impl FileCheckMode {
+    pub fn check(
+        &self,
+        file: &Path,
+        destination_file: &Path,
+        stored: Option<&FileMetadata>,
+    ) -> FileStatus {
+        let source_metadata = match self.get_metadata(file) {
+            Ok(source_metadata) => source_metadata,
+            _ => {
+                return FileStatus::Modified(
+                    format_modified(SystemTime::now()),
+                    UpdateReason::SourceChanged,
+                )
+            }
+        };
+
+        match stored {
+            Some(file_metadata) => {
+                if file_metadata.source != source_metadata {
+                    FileStatus::Modified(source_metadata, UpdateReason::SourceChanged)
+                } else if !self.output_up_to_date(file_metadata, destination_file) {
+                    FileStatus::Modified(source_metadata, UpdateReason::DestinationChanged)
+                } else {
+                    FileStatus::UpToDate
+                }
+            }
 
-    pub fn save(&self) -> Result<()> {
-        let mut file = FileChecker::get_file(&self.directory)?;
-        let mut contents = String::new();
+            _ => FileStatus::Modified(source_metadata, UpdateReason::New),
+        }
+    }
 
-        for (file_name, file_metadata) in &self.files {
-            contents.push_str(&format!("{};{}\n", file_name, file_metadata));
+    pub fn force_update(&self, file: &Path, reason: UpdateReason) -> FileStatus {
+        match self.get_metadata(file) {
+            Ok(source_metadata) => FileStatus::Modified(source_metadata, reason),
+            _ => FileStatus::Modified(format_modified(SystemTime::now()), reason),
         }
-        file.write_all(contents.as_bytes())
     }
 
-    pub fn add_file(&mut self, file: &PathBuf, system_time: SystemTime) {
-        self.files.insert(
-            file.to_str().unwrap().to_string(),
-            format!("{:?}", system_time),
-        );
+    pub fn output(&self, destination_file: &Path) -> Option<String> {
+        if !self.md5_output {
+            return None;
+        }
+
+        get_digest(destination_file).ok()
     }
 
-    pub fn remove_file(&mut self, file: &PathBuf) {
-        self.files.remove(&file.to_str().unwrap().to_string());
+    fn output_up_to_date(&self, file_metadata: &FileMetadata, destination_file: &Path) -> bool {
+        if !self.md5_output {
+            return true;
+        }
+
+        match (&file_metadata.output, get_digest(destination_file)) {
+            (Some(output), Ok(digest)) => *output == digest,
+            _ => false,
+        }
     }
 
-    pub fn force_update(&mut self, file: &PathBuf) -> FileStatus {
-        match FileChecker::get_modified(file) {
-            Ok(system_time) => FileStatus::Modified(system_time),
-            _ => FileStatus::Modified(SystemTime::now()),
+    fn get_metadata(&self, file: &Path) -> io::Result<String> {
+        if self.md5 {
+            return get_digest(file);
         }
+
+        Ok(format_modified(get_modified(file)?))
     }
 }
 
 impl FileChecker {
     fn default(directory: PathBuf) -> FileChecker {
         FileChecker {
-            directory: directory,
+            directory,
             files: HashMap::new(),
+            mode: FileCheckMode {
+                md5: false,
+                md5_output: false,
+            },
+            header: Vec::new(),
+            pending: 0,
+            last_saved: Instant::now(),
         }
     }
 
-    fn read_file(directory: &PathBuf) -> Result<String> {
-        let mut file = FileChecker::get_file(directory)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-
-        Ok(contents)
+    fn read_file(directory: &Path) -> io::Result<String> {
+        fs::read_to_string(directory.join(EXPORT_BRANCH_FILES_METADATA))
     }
 
-    fn get_file(directory: &PathBuf) -> Result<File> {
-        let file = Path::new(directory).join(EXPORT_BRANCH_FILES_METADATA);
+    fn build(directory: PathBuf, contents: String, header: &[(String, String)]) -> FileChecker {
+        let mut file_checker = FileChecker::default(directory);
+        let mut lines = contents.lines().peekable();
+
+        match lines.peek() {
+            Some(line) if line.starts_with(METADATA_HEADER) => {
+                let version = line[METADATA_HEADER.len()..].trim();
+
+                if version != METADATA_FORMAT_VERSION {
+                    return file_checker;
+                }
+
+                lines.next();
+            }
+            _ => {
+                file_checker.files = FileChecker::build_legacy(lines);
+                return file_checker;
+            }
+        }
+
+        let mut fingerprint = None;
+
+        for line in lines {
+            if let Some(header_line) = line.strip_prefix('#') {
+                if let Some(("fingerprint", value)) = header_line.split_once(' ') {
+                    fingerprint = Some(unescape(value));
+                }
+                continue;
+            }
+
+            let mut parts = split_escaped(line).into_iter();
+            if let (Some(file_name), Some(file_metadata)) = (parts.next(), parts.next()) {
+                file_checker.files.insert(
+                    file_name,
+                    FileMetadata {
+                        source: file_metadata,
+                        output: parts.next().filter(|x| !x.is_empty()),
+                        destination: parts.next().filter(|x| !x.is_empty()),
+                    },
+                );
+            }
+        }
+
+        let current_fingerprint = header
+            .iter()
+            .find(|(key, _)| key == "fingerprint")
+            .map(|(_, value)| value);
 
-        if !file.exists() {
-            return File::create(file);
+        if fingerprint.as_ref() != current_fingerprint {
+            file_checker.files.clear();
         }
 
-        File::options().read(true).write(true).open(file)
+        file_checker
     }
 
-    fn build(directory: PathBuf, contents: String) -> FileChecker {
+    fn build_legacy<'a>(lines: impl Iterator<Item = &'a str>) -> HashMap<String, FileMetadata> {
         let mut files = HashMap::new();
+        let formats = LegacyFormats {
+            unix: Regex::new(r"^SystemTime \{ tv_sec: (\d+), tv_nsec: (\d+) \}$").unwrap(),
+            windows: Regex::new(r"^SystemTime \{ intervals: (\d+) \}$").unwrap(),
+        };
 
-        for line in contents.lines() {
-            let mut parts = line.split(";");
+        for line in lines {
+            let mut parts = line.split(';');
             if let Some(file_name) = parts.next() {
                 if let Some(file_metadata) = parts.next() {
-                    files.insert(file_name.to_string(), file_metadata.to_string());
+                    files.insert(
+                        file_name.to_string(),
+                        FileMetadata {
+                            source: migrate_legacy_modified(file_metadata, &formats),
+                            output: parts.next().map(|x| x.to_string()),
+                            destination: None,
+                        },
+                    );
                 }
             };
         }
-        FileChecker { directory, files }
+
+        files
+    }
+
+    fn header(configuration: &Configuration) -> Vec<(String, String)> {
+        let mode = if configuration.md5() || configuration.md5_output() {
+            "md5"
+        } else {
+            "modified"
+        };
+        let mut hasher = Md5::new();
+
+        // Every setting that changes what is written to the destination. The
+        // exportbranch.exb directives, their conversion tables included, are
+        // read per directory by the walk, after this file, so changing one
+        // needs --reload.
+        hasher.update(format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{}\n{}\n{}\n{:?}",
+            configuration.convertions(),
+            configuration.file_filters(),
+            configuration.only_copy_files(),
+            configuration.exclusions(),
+            configuration.ignore_case(),
+            mode,
+            configuration.lower(),
+            configuration.disregarded_directories()
+        ));
+
+        vec![
+            ("tool".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+            ("mode".to_string(), mode.to_string()),
+            ("filters".to_string(), configuration.file_filters().join(";")),
+            ("only-copy".to_string(), configuration.only_copy_files().join(";")),
+            ("fingerprint".to_string(), to_hex(&hasher.finalize())),
+        ]
+    }
+}
+
+// The metadata file is keyed by path, so paths must be valid UTF-8.
+fn key(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| Error::not_utf8(path))
+}
+
+fn write_file(file_name: &Path, contents: &str) -> io::Result<()> {
+    let mut file = File::create(file_name)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+fn get_modified(file: &Path) -> io::Result<SystemTime> {
+    let metadata = file.metadata()?;
+    let modified = metadata.modified()?;
+    Ok(modified)
+}
+
+fn get_digest(file: &Path) -> io::Result<String> {
+    let mut hasher = Md5::new();
+    hasher.update(fs::read(file)?);
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn format_modified(system_time: SystemTime) -> String {
+    match system_time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => format_duration(duration),
+        Err(_) => format!("{:?}", system_time),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{}.{:09}", duration.as_secs(), duration.subsec_nanos())
+}
+
+// The Debug formats SystemTime had on Unix and Windows in the legacy metadata.
+struct LegacyFormats {
+    unix: Regex,
+    windows: Regex,
+}
+
+fn migrate_legacy_modified(file_metadata: &str, formats: &LegacyFormats) -> String {
+    if let Some(captures) = formats.unix.captures(file_metadata) {
+        if let (Ok(secs), Ok(nanos)) = (captures[1].parse::<u64>(), captures[2].parse::<u32>()) {
+            return format_duration(Duration::new(secs, nanos));
+        }
+    }
+
+    if let Some(captures) = formats.windows.captures(file_metadata) {
+        if let Ok(intervals) = captures[1].parse::<u64>() {
+            if let Some(intervals) = intervals.checked_sub(WINDOWS_TO_UNIX_EPOCH_INTERVALS) {
+                return format_duration(Duration::new(
+                    intervals / 10_000_000,
+                    (intervals % 10_000_000) as u32 * 100,
+                ));
+            }
+        }
+    }
+
+    file_metadata.to_string()
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    split_escaped(value).join(";")
+}
+
+fn split_escaped(line: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut characters = line.chars();
+
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => match characters.next() {
+                Some('n') => parts.last_mut().unwrap().push('\n'),
+                Some('r') => parts.last_mut().unwrap().push('\r'),
+                Some(escaped) => parts.last_mut().unwrap().push(escaped),
+                None => {}
+            },
+            ';' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(character),
+        }
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{self, TempDirectory};
+
+    fn configuration(source: &Path, options: &[&str]) -> Configuration {
+        let source = source.to_string_lossy();
+        let args = ["-s", &source, "-d", "destination"];
+
+        test_fixtures::configuration(&[&args, options].concat())
+    }
+
+    fn fingerprint(configuration: &Configuration) -> String {
+        FileChecker::header(configuration)
+            .into_iter()
+            .find(|(key, _)| key == "fingerprint")
+            .map(|(_, value)| value)
+            .unwrap()
+    }
+
+    #[test]
+    fn legacy_metadata_is_migrated() {
+        let files = FileChecker::build_legacy(
+            [
+                "/a.prg;SystemTime { tv_sec: 1700000000, tv_nsec: 5 }",
+                "/b.prg;SystemTime { intervals: 133000000000000000 }",
+                "/c.prg;unknown",
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(files["/a.prg"].source, "1700000000.000000005");
+        assert_eq!(files["/b.prg"].source, "1655526400.000000000");
+        assert_eq!(files["/c.prg"].source, "unknown");
+        assert!(files["/a.prg"].destination.is_none());
+    }
+
+    #[test]
+    fn only_the_current_version_and_headerless_files_are_read() {
+        let header = vec![("fingerprint".to_string(), "f".to_string())];
+        let read = |contents: &str| {
+            FileChecker::build(PathBuf::from("/d"), contents.to_string(), &header).files
+        };
+
+        let current = read("#exportbranch-metadata 3\n#fingerprint f\n/a.prg;1;;\n");
+        assert_eq!(current["/a.prg"].source, "1");
+        assert!(read("#exportbranch-metadata 2\n#fingerprint f\n/a.prg;1;;\n").is_empty());
+        assert!(read("#exportbranch-metadata 3\n#fingerprint g\n/a.prg;1;;\n").is_empty());
+        assert_eq!(read("/a.prg;unknown\n")["/a.prg"].source, "unknown");
     }
 
-    fn get_modified(file: &PathBuf) -> Result<SystemTime> {
-        let metadata = file.metadata()?;
-        let modified = metadata.modified()?;
-        Ok(modified)
+    #[test]
+    fn fingerprint_covers_the_output_settings() {
+        let directory = TempDirectory::new();
+        let source = directory.join("source");
+        fs::create_dir_all(source.join("lib")).unwrap();
+
+        let default = fingerprint(&configuration(&source, &[]));
+
+        assert_eq!(default, fingerprint(&configuration(&source, &[])));
+
+        for options in [
+            &["--lower"][..],
+            &["-x", "lib"],
+            &["-c", "*.txt"],
+            &["-e", "*.bak"],
+            &["--ignore-case"],
+        ] {
+            assert_ne!(default, fingerprint(&configuration(&source, options)));
+        }
     }
 }
@@ -0,0 +1,357 @@
+use crate::error::{Error, Result};
+use regex::{Regex, RegexBuilder};
+use std::path::{Path, PathBuf};
+
+const REGEX_PREFIX: &str = "re:";
+
+// A glob is matched against the file name, unless it contains a `/`, in which
+// case it is matched against the path relative to the directory it was
+// declared for (the source for the command line, the directory of the `.exb`).
+// A trailing `/` makes it match the directories containing the file instead.
+#[derive(Clone)]
+pub struct FilePattern {
+    text: String,
+    origin: String,
+    regex: Regex,
+    base: PathBuf,
+    path: bool,
+    directory: bool,
+}
+
+#[derive(Clone)]
+pub struct Exclusion {
+    pattern: FilePattern,
+    negated: bool,
+}
+
+impl FilePattern {
+    pub fn build(
+        pattern: &str,
+        base: &Path,
+        ignore_case: bool,
+        origin: &str,
+    ) -> Result<FilePattern> {
+        let (regex, path, directory) = match pattern.strip_prefix(REGEX_PREFIX) {
+            Some(regex) => (regex.to_string(), false, false),
+            None => {
+                let glob = pattern.strip_suffix('/').unwrap_or(pattern);
+
+                (
+                    glob_to_regex(glob.trim_start_matches('/'))?,
+                    glob.contains('/'),
+                    glob.len() < pattern.len(),
+                )
+            }
+        };
+
+        let regex = RegexBuilder::new(&regex)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|err| Error::Pattern(format!("Invalid pattern \"{}\": {}", pattern, err)))?;
+
+        Ok(FilePattern {
+            text: pattern.to_string(),
+            origin: origin.to_string(),
+            regex,
+            base: base.to_path_buf(),
+            path,
+            directory,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // Where the pattern was declared, for --explain.
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    pub fn is_match(&self, file: &Path) -> bool {
+        let relative = match file.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) if !self.path && !self.directory => file,
+            Err(_) => return false,
+        };
+
+        if self.directory {
+            return relative
+                .ancestors()
+                .skip(1)
+                .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                .any(|ancestor| self.is_match_relative(ancestor));
+        }
+
+        self.is_match_relative(relative)
+    }
+
+    pub fn is_match_entry(&self, path: &Path, is_directory: bool) -> bool {
+        if self.directory && !is_directory {
+            return false;
+        }
+
+        match path.strip_prefix(&self.base) {
+            Ok(relative) => self.is_match_relative(relative),
+            Err(_) => false,
+        }
+    }
+
+    fn is_match_relative(&self, relative: &Path) -> bool {
+        if self.path {
+            return self
+                .regex
+                .is_match(&relative.to_string_lossy().replace('\\', "/"));
+        }
+
+        match relative.file_name() {
+            Some(file_name) => self.regex.is_match(&file_name.to_string_lossy()),
+            None => false,
+        }
+    }
+}
+
+impl Exclusion {
+    pub fn build(
+        pattern: &str,
+        negated: bool,
+        base: &Path,
+        ignore_case: bool,
+        origin: &str,
+    ) -> Result<Exclusion> {
+        Ok(Exclusion {
+            pattern: FilePattern::build(pattern, base, ignore_case, origin)?,
+            negated,
+        })
+    }
+
+    pub fn pattern(&self) -> &FilePattern {
+        &self.pattern
+    }
+
+    pub fn text(&self) -> String {
+        match self.negated {
+            true => format!("!{}", self.pattern.text),
+            false => self.pattern.text.clone(),
+        }
+    }
+
+    pub fn is_match(&self, file: &Path) -> bool {
+        self.pattern.is_match(file)
+    }
+
+    pub fn is_match_entry(&self, path: &Path, is_directory: bool) -> bool {
+        self.pattern.is_match_entry(path, is_directory)
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+}
+
+pub fn build_patterns(
+    patterns: &[String],
+    base: &Path,
+    ignore_case: bool,
+    origin: &str,
+) -> Result<Vec<FilePattern>> {
+    patterns
+        .iter()
+        .map(|pattern| FilePattern::build(pattern, base, ignore_case, origin))
+        .collect()
+}
+
+pub fn build_exclusions(
+    patterns: &[String],
+    base: &Path,
+    ignore_case: bool,
+    origin: &str,
+) -> Result<Vec<Exclusion>> {
+    patterns
+        .iter()
+        .map(|pattern| build_exclusion(pattern, base, ignore_case, origin))
+        .collect()
+}
+
+pub fn build_exclusion(
+    pattern: &str,
+    base: &Path,
+    ignore_case: bool,
+    origin: &str,
+) -> Result<Exclusion> {
+    match pattern.strip_prefix('!') {
+        Some(pattern) => Exclusion::build(pattern, true, base, ignore_case, origin),
+        None => Exclusion::build(pattern, false, base, ignore_case, origin),
+    }
+}
+
+pub fn matching_pattern<'a>(file: &Path, patterns: &'a [FilePattern]) -> Option<&'a FilePattern> {
+    patterns.iter().find(|pattern| pattern.is_match(file))
+}
+
+// As in .gitignore, the last matching pattern decides.
+pub fn deciding_exclusion<'a>(file: &Path, exclusions: &'a [Exclusion]) -> Option<&'a Exclusion> {
+    exclusions
+        .iter()
+        .rev()
+        .find(|exclusion| exclusion.is_match(file))
+}
+
+pub fn is_excluded(file: &Path, exclusions: &[Exclusion]) -> bool {
+    deciding_exclusion(file, exclusions).is_some_and(|exclusion| !exclusion.negated)
+}
+
+fn glob_to_regex(glob: &str) -> Result<String> {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    let mut alternatives = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let mut class = String::from("[");
+
+                if matches!(chars.peek(), Some('!') | Some('^')) {
+                    chars.next();
+                    class.push('^');
+                }
+
+                let mut closed = false;
+                let mut first = true;
+
+                for c in chars.by_ref() {
+                    match c {
+                        ']' if !first => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' | '[' | ']' | '&' | '~' => {
+                            class.push('\\');
+                            class.push(c);
+                        }
+                        _ => class.push(c),
+                    }
+                    first = false;
+                }
+
+                if !closed {
+                    return Err(Error::Pattern(format!(
+                        "Invalid pattern \"{}\": unclosed [",
+                        glob
+                    )));
+                }
+
+                class.push(']');
+                regex.push_str(&class);
+            }
+            '{' => {
+                alternatives += 1;
+                regex.push_str("(?:");
+            }
+            ',' if alternatives > 0 => regex.push('|'),
+            '}' if alternatives > 0 => {
+                alternatives -= 1;
+                regex.push(')');
+            }
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    if alternatives > 0 {
+        return Err(Error::Pattern(format!(
+            "Invalid pattern \"{}\": unclosed {{",
+            glob
+        )));
+    }
+
+    regex.push('$');
+
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, file: &str) -> bool {
+        FilePattern::build(pattern, Path::new("/base"), false, "test")
+            .ok()
+            .unwrap()
+            .is_match(Path::new(file))
+    }
+
+    fn exclusions(patterns: &[&str]) -> Vec<Exclusion> {
+        let patterns: Vec<String> = patterns.iter().map(|x| x.to_string()).collect();
+        build_exclusions(&patterns, Path::new("/base"), false, "test")
+            .ok()
+            .unwrap()
+    }
+
+    #[test]
+    fn globs_become_anchored_regexes() {
+        assert_eq!(glob_to_regex("*.prg").ok().unwrap(), r"^[^/]*\.prg$");
+        assert_eq!(glob_to_regex("a?/**/b").ok().unwrap(), "^a[^/]/(?:.*/)?b$");
+        assert_eq!(glob_to_regex("[!a-c]").ok().unwrap(), "^[^a-c]$");
+        assert_eq!(glob_to_regex("{x,y}.h").ok().unwrap(), r"^(?:x|y)\.h$");
+        assert!(glob_to_regex("[abc").is_err());
+        assert!(glob_to_regex("{a,b").is_err());
+    }
+
+    #[test]
+    fn globs_match_file_names_or_relative_paths() {
+        assert!(matches("*.prg", "/base/lib/a.prg"));
+        assert!(!matches("*.prg", "/base/a.prgx"));
+        assert!(matches("lib/*.prg", "/base/lib/a.prg"));
+        assert!(!matches("lib/*.prg", "/base/lib/x/a.prg"));
+        assert!(!matches("lib/*.prg", "/other/lib/a.prg"));
+        assert!(matches("**/test/*.prg", "/base/test/a.prg"));
+        assert!(matches("**/test/*.prg", "/base/a/b/test/a.prg"));
+        assert!(matches("file?.[ch]", "/base/file1.c"));
+        assert!(!matches("file?.[ch]", "/base/file12.c"));
+        assert!(matches("{a,b}.prg", "/base/b.prg"));
+        assert!(!matches("{a,b}.prg", "/base/c.prg"));
+        assert!(matches("lib/", "/base/lib/x/a.prg"));
+        assert!(!matches("lib/", "/base/lib.prg"));
+        assert!(matches(r"re:^a\d\.prg$", "/base/x/a1.prg"));
+    }
+
+    #[test]
+    fn ignore_case_applies_to_globs() {
+        let pattern = FilePattern::build("*.PRG", Path::new("/base"), true, "test")
+            .ok()
+            .unwrap();
+
+        assert!(pattern.is_match(Path::new("/base/a.prg")));
+        assert!(!matches("*.PRG", "/base/a.prg"));
+    }
+
+    #[test]
+    fn the_last_matching_exclusion_decides() {
+        let keep = Path::new("/base/tests/keep.prg");
+        let other = Path::new("/base/tests/other.prg");
+
+        let excluded = exclusions(&["tests/", "!keep.prg"]);
+        assert!(!is_excluded(keep, &excluded));
+        assert!(is_excluded(other, &excluded));
+        assert_eq!(
+            deciding_exclusion(keep, &excluded).unwrap().text(),
+            "!keep.prg"
+        );
+
+        let excluded = exclusions(&["!keep.prg", "tests/"]);
+        assert!(is_excluded(keep, &excluded));
+        assert!(!is_excluded(Path::new("/base/keep.prg"), &excluded));
+    }
+}
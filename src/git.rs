@@ -0,0 +1,1034 @@
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub type ObjectId = [u8; 20];
+
+const OBJECT_COMMIT: u8 = 1;
+const OBJECT_TREE: u8 = 2;
+const OBJECT_BLOB: u8 = 3;
+const OBJECT_TAG: u8 = 4;
+const OBJECT_OFS_DELTA: u8 = 6;
+const OBJECT_REF_DELTA: u8 = 7;
+
+// The longest delta chain git writes, `pack.depth` being capped at 4095.
+const MAX_DELTA_DEPTH: usize = 4095;
+
+const MODE_TREE: u32 = 0o040000;
+const MODE_GITLINK: u32 = 0o160000;
+
+pub struct Repository {
+    pub work_tree: PathBuf,
+    pub git_directory: PathBuf,
+    packs: OnceCell<Vec<Pack>>,
+}
+
+pub struct IndexEntry {
+    pub path: String,
+    pub id: ObjectId,
+    pub modified: u32,
+    pub modified_nanoseconds: u32,
+    pub size: u32,
+}
+
+// Looks for the working tree containing the path. `.git` is usually a
+// directory, but worktrees and submodules use a file pointing to it.
+pub fn find_repository(path: &Path) -> Option<Repository> {
+    for work_tree in path.ancestors() {
+        let dot_git = work_tree.join(".git");
+
+        if dot_git.is_dir() {
+            return Some(Repository {
+                work_tree: work_tree.to_path_buf(),
+                git_directory: dot_git,
+                packs: OnceCell::new(),
+            });
+        }
+
+        if dot_git.is_file() {
+            let contents = fs::read_to_string(&dot_git).ok()?;
+            let git_directory = contents.trim().strip_prefix("gitdir:")?.trim();
+
+            return Some(Repository {
+                work_tree: work_tree.to_path_buf(),
+                git_directory: work_tree.join(git_directory),
+                packs: OnceCell::new(),
+            });
+        }
+    }
+
+    None
+}
+
+pub fn blob_id(contents: &[u8]) -> ObjectId {
+    let mut hasher = Sha1::new();
+
+    hasher.update(format!("blob {}\0", contents.len()).as_bytes());
+    hasher.update(contents);
+    hasher.finalize().into()
+}
+
+pub fn to_hex(id: &ObjectId) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Repository {
+    // Linked worktrees keep their own HEAD and index but share the objects
+    // and most refs with the main repository.
+    fn common_directory(&self) -> PathBuf {
+        match fs::read_to_string(self.git_directory.join("commondir")) {
+            Ok(common) => self.git_directory.join(common.trim()),
+            Err(_) => self.git_directory.clone(),
+        }
+    }
+
+    // Reads a value from the repository's config, then from the user's. Only
+    // sections without a subsection are understood, which is enough for core.
+    pub fn config(&self, section: &str, key: &str) -> Option<String> {
+        let mut files = vec![self.common_directory().join("config")];
+
+        if let Some(home) = env::var_os("HOME") {
+            files.push(PathBuf::from(home).join(".gitconfig"));
+        }
+
+        files
+            .iter()
+            .find_map(|file| config_value(&fs::read_to_string(file).ok()?, section, key))
+    }
+
+    pub fn index(&self) -> Result<Vec<IndexEntry>> {
+        let data = fs::read(self.git_directory.join("index"))?;
+
+        if data.len() < 12 || &data[0..4] != b"DIRC" {
+            return Err(invalid("the git index has no DIRC header"));
+        }
+
+        let version = read_u32(&data, 4)?;
+        let count = read_u32(&data, 8)? as usize;
+
+        if !(2..=4).contains(&version) {
+            return Err(invalid(&format!(
+                "unsupported git index version {}",
+                version
+            )));
+        }
+
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(count);
+        let mut offset = 12;
+        let mut previous_path: Vec<u8> = Vec::new();
+
+        for _ in 0..count {
+            let start = offset;
+            let modified = read_u32(&data, start + 8)?;
+            let modified_nanoseconds = read_u32(&data, start + 12)?;
+            let mode = read_u32(&data, start + 24)?;
+            let size = read_u32(&data, start + 36)?;
+            let id = read_id(&data, start + 40)?;
+            let flags = read_u16(&data, start + 60)?;
+            let stage = (flags >> 12) & 3;
+
+            offset = start + 62;
+
+            if version >= 3 && flags & 0x4000 != 0 {
+                offset += 2;
+            }
+
+            let path = if version == 4 {
+                let (strip, length) = read_offset_varint(&data, offset)?;
+                offset += length;
+
+                let end = find_nul(&data, offset)?;
+                let keep = previous_path
+                    .len()
+                    .checked_sub(strip as usize)
+                    .ok_or_else(|| invalid("corrupt git index path"))?;
+                let mut path = previous_path[..keep].to_vec();
+
+                path.extend_from_slice(&data[offset..end]);
+                offset = end + 1;
+                path
+            } else {
+                let end = find_nul(&data, offset)?;
+                let path = data[offset..end].to_vec();
+
+                offset = start + (end - start + 8) / 8 * 8;
+                path
+            };
+
+            previous_path = path.clone();
+
+            // Conflicted paths appear once per stage and submodules have no
+            // file to export.
+            if stage > 1 || mode == MODE_GITLINK {
+                continue;
+            }
+
+            let path = String::from_utf8_lossy(&path).to_string();
+
+            if entries.last().is_some_and(|last| last.path == path) {
+                continue;
+            }
+
+            entries.push(IndexEntry {
+                path,
+                id,
+                modified,
+                modified_nanoseconds,
+                size,
+            });
+        }
+
+        // A split index keeps most entries in a shared file and a sparse one
+        // replaces whole directories by a tree entry, so in both the entries
+        // read above are not the files in the working tree.
+        while offset + 8 <= data.len().saturating_sub(20) {
+            let signature = &data[offset..offset + 4];
+
+            if signature == b"link" || signature == b"sdir" {
+                return Err(invalid(&format!(
+                    "unsupported git index extension {}",
+                    String::from_utf8_lossy(signature)
+                )));
+            }
+
+            offset += 8 + read_u32(&data, offset + 4)? as usize;
+        }
+
+        Ok(entries)
+    }
+
+    // Understands HEAD, branch, tag and remote names, full or abbreviated
+    // object ids and the `~n`, `^` and `^n` suffixes.
+    pub fn resolve(&self, revision: &str) -> Result<ObjectId> {
+        let split = revision.find(['~', '^']).unwrap_or(revision.len());
+        let (name, mut suffixes) = revision.split_at(split);
+
+        let mut id = match self.resolve_name(name)? {
+            Some(id) => self.peel(id)?,
+            None => return Err(invalid(&format!("unknown revision \"{}\"", revision))),
+        };
+
+        while !suffixes.is_empty() {
+            let operator = suffixes.as_bytes()[0];
+            let digits: String = suffixes[1..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            let number = digits.parse::<usize>().unwrap_or(1);
+
+            suffixes = &suffixes[1 + digits.len()..];
+
+            if operator == b'~' {
+                for _ in 0..number {
+                    id = self.parent(&id, 1, revision)?;
+                }
+            } else if number > 0 {
+                id = self.parent(&id, number, revision)?;
+            }
+        }
+
+        Ok(id)
+    }
+
+    pub fn tree_files(&self, commit: &ObjectId) -> Result<HashMap<String, ObjectId>> {
+        let (kind, data) = self.read_object(commit)?;
+
+        if kind != OBJECT_COMMIT {
+            return Err(invalid(&format!("{} is not a commit", to_hex(commit))));
+        }
+
+        let tree = header_field(&data, "tree")
+            .and_then(|tree| parse_hex(&tree))
+            .ok_or_else(|| invalid(&format!("commit {} has no tree", to_hex(commit))))?;
+
+        let mut files = HashMap::new();
+        self.collect_tree(&tree, "", &mut files)?;
+
+        Ok(files)
+    }
+
+    fn collect_tree(
+        &self,
+        tree: &ObjectId,
+        prefix: &str,
+        files: &mut HashMap<String, ObjectId>,
+    ) -> Result<()> {
+        let (kind, data) = self.read_object(tree)?;
+
+        if kind != OBJECT_TREE {
+            return Err(invalid(&format!("{} is not a tree", to_hex(tree))));
+        }
+
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let space = data[offset..]
+                .iter()
+                .position(|byte| *byte == b' ')
+                .ok_or_else(|| invalid("corrupt git tree"))?;
+            let mode =
+                u32::from_str_radix(&String::from_utf8_lossy(&data[offset..offset + space]), 8)
+                    .map_err(|_| invalid("corrupt git tree"))?;
+            let end = find_nul(&data, offset + space + 1)?;
+            let name = String::from_utf8_lossy(&data[offset + space + 1..end]);
+            let id = read_id(&data, end + 1)?;
+            let path = format!("{}{}", prefix, name);
+
+            offset = end + 21;
+
+            if mode == MODE_TREE {
+                self.collect_tree(&id, &format!("{}/", path), files)?;
+            } else if mode != MODE_GITLINK {
+                files.insert(path, id);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parent(&self, commit: &ObjectId, number: usize, revision: &str) -> Result<ObjectId> {
+        let (_, data) = self.read_object(commit)?;
+
+        String::from_utf8_lossy(&data)
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.strip_prefix("parent "))
+            .nth(number - 1)
+            .and_then(parse_hex)
+            .ok_or_else(|| invalid(&format!("revision \"{}\" has no such parent", revision)))
+    }
+
+    fn peel(&self, id: ObjectId) -> Result<ObjectId> {
+        let mut id = id;
+
+        loop {
+            let (kind, data) = self.read_object(&id)?;
+
+            match kind {
+                OBJECT_COMMIT => return Ok(id),
+                OBJECT_TAG => {
+                    id = header_field(&data, "object")
+                        .and_then(|object| parse_hex(&object))
+                        .ok_or_else(|| invalid(&format!("corrupt tag {}", to_hex(&id))))?;
+                }
+                _ => return Err(invalid(&format!("{} is not a commit", to_hex(&id)))),
+            }
+        }
+    }
+
+    fn resolve_name(&self, name: &str) -> Result<Option<ObjectId>> {
+        let name = if name.is_empty() || name == "@" {
+            "HEAD"
+        } else {
+            name
+        };
+
+        for reference in [
+            name.to_string(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ] {
+            if let Some(id) = self.read_reference(&reference, 0)? {
+                return Ok(Some(id));
+            }
+        }
+
+        if name.len() >= 4 && name.len() <= 40 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            return self.find_abbreviated(&name.to_lowercase());
+        }
+
+        Ok(None)
+    }
+
+    fn read_reference(&self, reference: &str, depth: usize) -> Result<Option<ObjectId>> {
+        if depth > 10 {
+            return Err(invalid(&format!("reference loop at \"{}\"", reference)));
+        }
+
+        let directory = if reference.starts_with("refs/") {
+            self.common_directory()
+        } else {
+            self.git_directory.clone()
+        };
+
+        if let Ok(contents) = fs::read_to_string(directory.join(reference)) {
+            let contents = contents.trim();
+
+            return match contents.strip_prefix("ref:") {
+                Some(target) => self.read_reference(target.trim(), depth + 1),
+                None => Ok(parse_hex(contents)),
+            };
+        }
+
+        if let Ok(packed) = fs::read_to_string(self.common_directory().join("packed-refs")) {
+            for line in packed.lines() {
+                if let Some((id, name)) = line.split_once(' ') {
+                    if name == reference {
+                        return Ok(parse_hex(id));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn find_abbreviated(&self, prefix: &str) -> Result<Option<ObjectId>> {
+        let mut found: Vec<ObjectId> = Vec::new();
+        let objects = self.common_directory().join("objects");
+
+        if let Ok(entries) = fs::read_dir(objects.join(&prefix[..2])) {
+            for entry in entries.flatten() {
+                let name = format!("{}{}", &prefix[..2], entry.file_name().to_string_lossy());
+
+                if name.starts_with(prefix) {
+                    found.extend(parse_hex(&name));
+                }
+            }
+        }
+
+        for pack in self.packs()? {
+            for id in pack.ids()? {
+                if to_hex(&id).starts_with(prefix) && !found.contains(&id) {
+                    found.push(id);
+                }
+            }
+        }
+
+        match found.len() {
+            0 => Ok(None),
+            1 => Ok(Some(found[0])),
+            _ => Err(invalid(&format!("short object id {} is ambiguous", prefix))),
+        }
+    }
+
+    pub fn read_object(&self, id: &ObjectId) -> Result<(u8, Vec<u8>)> {
+        self.read_object_at_depth(id, 0)
+    }
+
+    fn read_object_at_depth(&self, id: &ObjectId, depth: usize) -> Result<(u8, Vec<u8>)> {
+        let hex = to_hex(id);
+        let loose = self
+            .common_directory()
+            .join("objects")
+            .join(&hex[..2])
+            .join(&hex[2..]);
+
+        if loose.is_file() {
+            let mut data = Vec::new();
+            ZlibDecoder::new(fs::File::open(loose)?).read_to_end(&mut data)?;
+
+            let end = find_nul(&data, 0)?;
+            let header = String::from_utf8_lossy(&data[..end]).to_string();
+            let kind = match header.split(' ').next() {
+                Some("commit") => OBJECT_COMMIT,
+                Some("tree") => OBJECT_TREE,
+                Some("blob") => OBJECT_BLOB,
+                Some("tag") => OBJECT_TAG,
+                _ => return Err(invalid(&format!("corrupt object {}", hex))),
+            };
+
+            return Ok((kind, data.split_off(end + 1)));
+        }
+
+        for pack in self.packs()? {
+            if let Some(offset) = pack.find(id)? {
+                return pack.read(self, offset, depth);
+            }
+        }
+
+        Err(invalid(&format!("object {} not found", hex)))
+    }
+
+    fn packs(&self) -> Result<&[Pack]> {
+        if let Some(packs) = self.packs.get() {
+            return Ok(packs);
+        }
+
+        let directory = self.common_directory().join("objects").join("pack");
+        let mut packs = Vec::new();
+
+        if let Ok(entries) = fs::read_dir(&directory) {
+            let mut indexes: Vec<PathBuf> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+                .collect();
+            indexes.sort();
+
+            for index in indexes {
+                packs.push(Pack::open(index)?);
+            }
+        }
+
+        Ok(self.packs.get_or_init(|| packs))
+    }
+}
+
+struct Pack {
+    index: Vec<u8>,
+    count: usize,
+    pack: PathBuf,
+    file: RefCell<Option<BufReader<fs::File>>>,
+}
+
+enum PackEntry {
+    Object(u8, Vec<u8>),
+    OffsetDelta(u64, Vec<u8>),
+    ReferenceDelta(ObjectId, Vec<u8>),
+}
+
+impl Pack {
+    fn open(index: PathBuf) -> Result<Pack> {
+        let data = fs::read(&index)?;
+
+        if data.len() < 8 + 256 * 4 || data[0..8] != [0xff, 0x74, 0x4f, 0x63, 0, 0, 0, 2] {
+            return Err(invalid(&format!("unsupported pack index {:?}", index)));
+        }
+
+        Ok(Pack {
+            count: read_u32(&data, 8 + 255 * 4)? as usize,
+            index: data,
+            pack: index.with_extension("pack"),
+            file: RefCell::new(None),
+        })
+    }
+
+    fn ids(&self) -> Result<Vec<ObjectId>> {
+        (0..self.count)
+            .map(|i| read_id(&self.index, 8 + 256 * 4 + i * 20))
+            .collect()
+    }
+
+    fn find(&self, id: &ObjectId) -> Result<Option<u64>> {
+        let data = self.index.as_slice();
+        let count = self.count;
+        let ids = 8 + 256 * 4;
+        let first = id[0] as usize;
+        let mut low = match first {
+            0 => 0,
+            _ => read_u32(data, 8 + (first - 1) * 4)? as usize,
+        };
+        let mut high = read_u32(data, 8 + first * 4)? as usize;
+
+        while low < high {
+            let middle = (low + high) / 2;
+            let candidate = read_id(data, ids + middle * 20)?;
+
+            match candidate.cmp(id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    let offsets = ids + count * 24;
+                    let offset = read_u32(data, offsets + middle * 4)?;
+
+                    if offset & 0x8000_0000 == 0 {
+                        return Ok(Some(offset as u64));
+                    }
+
+                    let large = offsets + count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+                    return Ok(Some(
+                        (read_u32(data, large)? as u64) << 32 | read_u32(data, large + 4)? as u64,
+                    ));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // A delta is read after its base, which may itself be a delta.
+    fn read(&self, repository: &Repository, offset: u64, depth: usize) -> Result<(u8, Vec<u8>)> {
+        if depth > MAX_DELTA_DEPTH {
+            return Err(invalid(&format!(
+                "delta chain longer than {} in pack {:?}",
+                MAX_DELTA_DEPTH, self.pack
+            )));
+        }
+
+        match self.read_entry(offset)? {
+            PackEntry::Object(kind, data) => Ok((kind, data)),
+            PackEntry::OffsetDelta(base, delta) => {
+                let (kind, base) = self.read(repository, base, depth + 1)?;
+
+                Ok((kind, apply_delta(&base, &delta)?))
+            }
+            PackEntry::ReferenceDelta(base, delta) => {
+                let (kind, base) = repository.read_object_at_depth(&base, depth + 1)?;
+
+                Ok((kind, apply_delta(&base, &delta)?))
+            }
+        }
+    }
+
+    // The pack stays open for the next read, which is usually in the same one.
+    fn read_entry(&self, offset: u64) -> Result<PackEntry> {
+        let mut file = self.file.borrow_mut();
+        let reader = match file.as_mut() {
+            Some(reader) => reader,
+            None => file.insert(BufReader::new(fs::File::open(&self.pack)?)),
+        };
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut byte = read_byte(reader)?;
+        let kind = (byte >> 4) & 7;
+
+        while byte & 0x80 != 0 {
+            byte = read_byte(reader)?;
+        }
+
+        match kind {
+            OBJECT_OFS_DELTA => {
+                byte = read_byte(reader)?;
+                let mut distance = (byte & 0x7f) as u64;
+
+                while byte & 0x80 != 0 {
+                    byte = read_byte(reader)?;
+                    distance = next_offset_digit(distance, byte)
+                        .ok_or_else(|| invalid("corrupt pack delta"))?;
+                }
+
+                // The base comes before the delta, so a zero distance would loop.
+                let base = offset
+                    .checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .ok_or_else(|| invalid("corrupt pack delta"))?;
+
+                Ok(PackEntry::OffsetDelta(base, inflate(reader)?))
+            }
+            OBJECT_REF_DELTA => {
+                let mut base = [0u8; 20];
+                reader.read_exact(&mut base)?;
+
+                Ok(PackEntry::ReferenceDelta(base, inflate(reader)?))
+            }
+            OBJECT_COMMIT | OBJECT_TREE | OBJECT_BLOB | OBJECT_TAG => {
+                Ok(PackEntry::Object(kind, inflate(reader)?))
+            }
+            _ => Err(invalid(&format!("corrupt pack {:?}", self.pack))),
+        }
+    }
+}
+
+fn inflate(reader: impl Read) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(reader).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut offset = 0;
+    let base_size = read_size_varint(delta, &mut offset)?;
+    let size = read_size_varint(delta, &mut offset)?;
+
+    if base_size != base.len() {
+        return Err(invalid("corrupt pack delta"));
+    }
+
+    // The size is only trusted once the result has been built.
+    let mut result = Vec::with_capacity(size.min(base.len() + delta.len()));
+
+    while offset < delta.len() {
+        let instruction = delta[offset];
+        offset += 1;
+
+        if instruction & 0x80 != 0 {
+            let mut copy_offset = 0usize;
+            let mut copy_size = 0usize;
+
+            for bit in 0..7 {
+                if instruction & (1 << bit) != 0 {
+                    let byte = *delta
+                        .get(offset)
+                        .ok_or_else(|| invalid("corrupt pack delta"))?
+                        as usize;
+                    offset += 1;
+
+                    if bit < 4 {
+                        copy_offset |= byte << (8 * bit);
+                    } else {
+                        copy_size |= byte << (8 * (bit - 4));
+                    }
+                }
+            }
+
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+
+            result.extend_from_slice(
+                copy_offset
+                    .checked_add(copy_size)
+                    .and_then(|end| base.get(copy_offset..end))
+                    .ok_or_else(|| invalid("corrupt pack delta"))?,
+            );
+        } else if instruction != 0 {
+            let end = offset + instruction as usize;
+
+            result.extend_from_slice(
+                delta
+                    .get(offset..end)
+                    .ok_or_else(|| invalid("corrupt pack delta"))?,
+            );
+            offset = end;
+        } else {
+            return Err(invalid("corrupt pack delta"));
+        }
+    }
+
+    if result.len() != size {
+        return Err(invalid("corrupt pack delta"));
+    }
+
+    Ok(result)
+}
+
+fn config_value(contents: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    let mut value = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if let Some(header) = line.strip_prefix('[') {
+            in_section = header
+                .strip_suffix(']')
+                .is_some_and(|header| header.trim().eq_ignore_ascii_case(section));
+            continue;
+        }
+
+        if !in_section {
+            continue;
+        }
+
+        let (name, rest) = line.split_once('=').unwrap_or((line, "true"));
+
+        if name.trim().eq_ignore_ascii_case(key) {
+            let rest = rest.split(['#', ';']).next().unwrap_or("");
+            value = Some(rest.trim().trim_matches('"').to_string());
+        }
+    }
+
+    value
+}
+
+fn header_field(data: &[u8], field: &str) -> Option<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix(&format!("{} ", field)).map(String::from))
+}
+
+fn parse_hex(hex: &str) -> Option<ObjectId> {
+    let hex = hex.trim();
+
+    if hex.len() != 40 {
+        return None;
+    }
+
+    let mut id = [0u8; 20];
+
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(id)
+}
+
+fn read_byte(reader: &mut impl Read) -> Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(invalid("unexpected end of git data")),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(invalid("unexpected end of git data")),
+    }
+}
+
+fn read_id(data: &[u8], offset: usize) -> Result<ObjectId> {
+    match data.get(offset..offset + 20) {
+        Some(bytes) => {
+            let mut id = [0u8; 20];
+            id.copy_from_slice(bytes);
+            Ok(id)
+        }
+        None => Err(invalid("unexpected end of git data")),
+    }
+}
+
+fn find_nul(data: &[u8], offset: usize) -> Result<usize> {
+    data.get(offset..)
+        .and_then(|rest| rest.iter().position(|byte| *byte == 0))
+        .map(|position| offset + position)
+        .ok_or_else(|| invalid("unexpected end of git data"))
+}
+
+// The prefix length of a version 4 index path uses the same encoding as the
+// offset of a pack delta base.
+fn read_offset_varint(data: &[u8], offset: usize) -> Result<(u64, usize)> {
+    let mut length = 0;
+    let mut byte = *data
+        .get(offset)
+        .ok_or_else(|| invalid("corrupt git index"))?;
+    let mut value = (byte & 0x7f) as u64;
+
+    while byte & 0x80 != 0 {
+        length += 1;
+        byte = *data
+            .get(offset + length)
+            .ok_or_else(|| invalid("corrupt git index"))?;
+        value = next_offset_digit(value, byte).ok_or_else(|| invalid("corrupt git index"))?;
+    }
+
+    Ok((value, length + 1))
+}
+
+// None when the value no longer fits, which only happens in corrupt data.
+fn next_offset_digit(value: u64, byte: u8) -> Option<u64> {
+    value
+        .checked_add(1)?
+        .checked_mul(1 << 7)
+        .map(|value| value | (byte & 0x7f) as u64)
+}
+
+fn read_size_varint(data: &[u8], offset: &mut usize) -> Result<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+
+    loop {
+        let byte = *data
+            .get(*offset)
+            .ok_or_else(|| invalid("corrupt pack delta"))?;
+        let bits = (byte & 0x7f) as usize;
+        *offset += 1;
+
+        // Rejects sizes with more bits than usize, however many bytes encode them.
+        value |= bits
+            .checked_shl(shift)
+            .filter(|shifted| shifted >> shift == bits)
+            .ok_or_else(|| invalid("corrupt pack delta"))?;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{git, TempDirectory};
+
+    // A repository built with the git command line, so the objects and the
+    // index are in the formats git really writes.
+    fn fixture() -> TempDirectory {
+        let directory = TempDirectory::new();
+        let root = directory.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+
+        git(root, &["init", "-q"]);
+
+        for revision in 0..20 {
+            let lines: String = (0..200)
+                .map(|line| format!("line {} of revision {}\n", line, revision.min(line)))
+                .collect();
+            fs::write(root.join("src").join("main.prg"), lines).unwrap();
+            fs::write(root.join("README"), format!("revision {}\n", revision)).unwrap();
+
+            git(root, &["add", "-A"]);
+            git(
+                root,
+                &["commit", "-q", "-m", &format!("revision {}", revision)],
+            );
+        }
+
+        git(root, &["tag", "-a", "-m", "first", "first", "HEAD~19"]);
+        directory
+    }
+
+    // Every revision git knows has the same files and blobs for us.
+    fn assert_reads_like_git(root: &Path) {
+        let repository = find_repository(root).unwrap();
+
+        for revision in ["HEAD", "HEAD~7", "HEAD^^", "first", "master~19"] {
+            let id = repository.resolve(revision).ok().unwrap();
+            assert_eq!(
+                to_hex(&id),
+                git(root, &["rev-parse", &format!("{}^{{commit}}", revision)]).trim()
+            );
+
+            let files = repository.tree_files(&id).ok().unwrap();
+            let listed = git(root, &["ls-tree", "-r", revision]);
+            assert_eq!(files.len(), listed.lines().count());
+
+            for line in listed.lines() {
+                let (object, path) = line.split_once('\t').unwrap();
+                let blob = object.split(' ').nth(2).unwrap();
+                let (kind, data) = repository.read_object(&files[path]).ok().unwrap();
+
+                assert_eq!(to_hex(&files[path]), blob);
+                assert_eq!(kind, OBJECT_BLOB);
+                assert_eq!(blob_id(&data), files[path]);
+            }
+        }
+    }
+
+    fn assert_index_like_git(root: &Path) {
+        let index = find_repository(root).unwrap().index().ok().unwrap();
+        let staged = git(root, &["ls-files", "-s"]);
+
+        assert_eq!(index.len(), staged.lines().count());
+
+        for (entry, line) in index.iter().zip(staged.lines()) {
+            let (stage, path) = line.split_once('\t').unwrap();
+
+            assert_eq!(entry.path, path);
+            assert_eq!(to_hex(&entry.id), stage.split(' ').nth(1).unwrap());
+        }
+    }
+
+    #[test]
+    fn reads_loose_objects() {
+        let directory = fixture();
+        let root = directory.path();
+
+        assert_reads_like_git(root);
+    }
+
+    #[test]
+    fn reads_packs_with_offset_deltas() {
+        let directory = fixture();
+        let root = directory.path();
+        git(root, &["repack", "-a", "-d", "-f", "-q"]);
+
+        assert!(
+            fs::read_dir(root.join(".git/objects/pack"))
+                .unwrap()
+                .count()
+                > 0
+        );
+        assert_reads_like_git(root);
+    }
+
+    #[test]
+    fn reads_packs_with_reference_deltas() {
+        let directory = fixture();
+        let root = directory.path();
+        git(
+            root,
+            &[
+                "-c",
+                "repack.useDeltaBaseOffset=false",
+                "repack",
+                "-a",
+                "-d",
+                "-f",
+                "-q",
+            ],
+        );
+
+        assert_reads_like_git(root);
+    }
+
+    #[test]
+    fn reads_every_index_version() {
+        let directory = fixture();
+        let root = directory.path();
+
+        for name in ["a", "b/c", "b/d/e", "b/d/f", "g"] {
+            let file = root.join(name);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, name).unwrap();
+        }
+        git(root, &["add", "a", "b"]);
+        // An intent-to-add entry has the extended flags of version 3.
+        git(root, &["add", "-N", "g"]);
+
+        for version in ["2", "3", "4"] {
+            git(root, &["update-index", "--index-version", version]);
+            assert_index_like_git(root);
+        }
+    }
+
+    #[test]
+    fn split_and_sparse_indexes_are_errors() {
+        let directory = fixture();
+        let root = directory.path();
+        let repository = find_repository(root).unwrap();
+
+        git(root, &["update-index", "--split-index"]);
+        assert!(repository.index().is_err());
+
+        git(root, &["update-index", "--no-split-index"]);
+        assert_index_like_git(root);
+
+        git(root, &["sparse-checkout", "set", "--sparse-index", "src"]);
+        assert!(repository.index().is_err());
+    }
+
+    #[test]
+    fn delta_chains_are_bounded() {
+        let directory = fixture();
+        let root = directory.path();
+        git(root, &["repack", "-a", "-d", "-f", "-q"]);
+
+        let repository = find_repository(root).unwrap();
+        let pack = &repository.packs().ok().unwrap()[0];
+        let offset = pack
+            .ids()
+            .ok()
+            .unwrap()
+            .iter()
+            .map(|id| pack.find(id).ok().unwrap().unwrap())
+            .find(|offset| matches!(pack.read_entry(*offset), Ok(PackEntry::Object(..))))
+            .unwrap();
+
+        assert!(pack.read(&repository, offset, MAX_DELTA_DEPTH).is_ok());
+        assert!(pack.read(&repository, offset, MAX_DELTA_DEPTH + 1).is_err());
+    }
+
+    #[test]
+    fn corrupt_varints_are_errors() {
+        let mut offset = 0;
+        let long = [0xffu8; 12];
+        assert!(read_size_varint(&long, &mut offset).is_err());
+
+        let mut offset = 0;
+        let zeros = [
+            0x80u8, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0,
+        ];
+        assert!(read_size_varint(&zeros, &mut offset).is_err());
+
+        assert!(read_offset_varint(&long, 0).is_err());
+        assert!(apply_delta(b"base", &long).is_err());
+        // Base size 4, result size 8, copy 4 bytes from offset 2 of the base.
+        assert!(apply_delta(b"base", &[4, 8, 0x91, 2, 4]).is_err());
+        assert_eq!(
+            apply_delta(b"base", &[4, 2, 0x91, 2, 2]).ok().unwrap(),
+            b"se"
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use crate::file_pattern::FilePattern;
+use crate::git::{blob_id, find_repository, IndexEntry, ObjectId, Repository};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GITATTRIBUTES: &str = ".gitattributes";
+
+pub struct GitFiles {
+    tracked: HashSet<PathBuf>,
+    directories: HashSet<PathBuf>,
+    changed: Option<HashSet<PathBuf>>,
+}
+
+pub enum GitStatus {
+    Untracked,
+    Unchanged,
+    Changed,
+    Tracked,
+}
+
+impl GitFiles {
+    // Reads the tracked files of the repository containing the source from its
+    // index and, with a revision, which of them differ from that revision.
+    pub fn read(source: &Path, since: Option<&str>) -> Result<GitFiles, String> {
+        let repository = find_repository(source)
+            .ok_or_else(|| format!("{:?} is not inside a git repository", source))?;
+        let index = repository
+            .index()
+            .map_err(|err| format!("Failed to read the git index: {}", err))?;
+
+        let changed = match since {
+            Some(revision) => Some(
+                changed_files(&repository, &index, revision)
+                    .map_err(|err| format!("Failed to compare with \"{}\": {}", revision, err))?,
+            ),
+            None => None,
+        };
+
+        let mut tracked = HashSet::new();
+        let mut directories = HashSet::new();
+
+        for entry in &index {
+            let file = repository.work_tree.join(&entry.path);
+
+            if !file.starts_with(source) {
+                continue;
+            }
+
+            directories.extend(
+                file.ancestors()
+                    .skip(1)
+                    .take_while(|directory| directory.starts_with(source))
+                    .map(Path::to_path_buf),
+            );
+            tracked.insert(file);
+        }
+
+        Ok(GitFiles {
+            tracked,
+            directories,
+            changed: changed.map(|changed| {
+                changed
+                    .iter()
+                    .map(|path| repository.work_tree.join(path))
+                    .collect()
+            }),
+        })
+    }
+
+    pub fn has_tracked_files(&self, directory: &Path) -> bool {
+        self.directories.contains(directory)
+    }
+
+    pub fn status(&self, file: &Path) -> GitStatus {
+        if !self.tracked.contains(file) {
+            return GitStatus::Untracked;
+        }
+
+        match &self.changed {
+            Some(changed) if changed.contains(file) => GitStatus::Changed,
+            Some(_) => GitStatus::Unchanged,
+            None => GitStatus::Tracked,
+        }
+    }
+}
+
+fn changed_files(
+    repository: &Repository,
+    index: &[IndexEntry],
+    revision: &str,
+) -> std::io::Result<HashSet<String>> {
+    let commit = repository.resolve(revision)?;
+    let files: HashMap<String, ObjectId> = repository.tree_files(&commit)?;
+    let attributes = Attributes::read(repository, index);
+    let index_modified = fs::metadata(repository.git_directory.join("index"))
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let mut changed = HashSet::new();
+
+    for entry in index {
+        let id = match files.get(&entry.path) {
+            Some(id) => id,
+            None => {
+                changed.insert(entry.path.clone());
+                continue;
+            }
+        };
+
+        if !same_content(repository, &attributes, index_modified, entry, id) {
+            changed.insert(entry.path.clone());
+        }
+    }
+
+    Ok(changed)
+}
+
+// When the index already records the revision's blob and the file still has
+// the size and modification time git saw, its content is not read again.
+// As in git, a file modified after the index was written is read anyway, it
+// may have changed within the timestamp's resolution.
+fn same_content(
+    repository: &Repository,
+    attributes: &Attributes,
+    index_modified: Option<SystemTime>,
+    entry: &IndexEntry,
+    id: &ObjectId,
+) -> bool {
+    let file = repository.work_tree.join(&entry.path);
+
+    let metadata = match fs::symlink_metadata(&file) {
+        Ok(metadata) => metadata,
+        Err(_) => return true,
+    };
+
+    if entry.id == *id && metadata.len() as u32 == entry.size {
+        let modified = metadata.modified().ok();
+        let racy = match (modified, index_modified) {
+            (Some(modified), Some(index_modified)) => modified >= index_modified,
+            _ => true,
+        };
+        let stat = modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| (modified.as_secs() as u32, modified.subsec_nanos()));
+
+        if !racy && stat == Some((entry.modified, entry.modified_nanoseconds)) {
+            return true;
+        }
+    }
+
+    if metadata.is_symlink() {
+        return match fs::read_link(&file) {
+            Ok(target) => blob_id(target.to_string_lossy().as_bytes()) == *id,
+            Err(_) => false,
+        };
+    }
+
+    let contents = match fs::read(&file) {
+        Ok(contents) => contents,
+        Err(_) => return false,
+    };
+
+    if blob_id(&contents) == *id {
+        return true;
+    }
+
+    // git stores text files with LF line endings when told to convert them.
+    let converted = match attributes.conversion(&file) {
+        TextConversion::Text => true,
+        TextConversion::Auto => !is_binary(&contents),
+        TextConversion::None => false,
+    };
+
+    converted && blob_id(&crlf_to_lf(&contents)) == *id
+}
+
+#[derive(Clone, Copy)]
+enum TextConversion {
+    None,
+    Text,
+    Auto,
+}
+
+struct Attribute {
+    pattern: FilePattern,
+    text: Option<TextConversion>,
+    eol: bool,
+}
+
+// The `text`, `binary` and `eol` attributes of the .gitattributes files in the
+// index and of .git/info/attributes, with core.autocrlf for the files that
+// have none of them.
+struct Attributes {
+    attributes: Vec<Attribute>,
+    autocrlf: bool,
+}
+
+impl Attributes {
+    fn read(repository: &Repository, index: &[IndexEntry]) -> Attributes {
+        let mut files: Vec<(PathBuf, PathBuf)> = index
+            .iter()
+            .filter(|entry| entry.path.rsplit('/').next() == Some(GITATTRIBUTES))
+            .map(|entry| {
+                let file = repository.work_tree.join(&entry.path);
+                let base = file.parent().unwrap_or(&repository.work_tree).to_path_buf();
+                (file, base)
+            })
+            .collect();
+        // Deeper files come later and so take precedence, the repository's own
+        // attributes last.
+        files.sort_by_key(|(file, _)| file.components().count());
+        files.push((
+            repository.git_directory.join("info").join("attributes"),
+            repository.work_tree.clone(),
+        ));
+
+        let mut attributes = Vec::new();
+
+        for (file, base) in files {
+            if let Ok(contents) = fs::read_to_string(&file) {
+                read_attributes(&contents, &base, &file, &mut attributes);
+            }
+        }
+
+        Attributes {
+            attributes,
+            autocrlf: repository
+                .config("core", "autocrlf")
+                .is_some_and(|value| value == "true" || value == "input"),
+        }
+    }
+
+    fn conversion(&self, file: &Path) -> TextConversion {
+        let matching = || {
+            self.attributes
+                .iter()
+                .rev()
+                .filter(|attribute| attribute.pattern.is_match(file))
+        };
+
+        if let Some(text) = matching().find_map(|attribute| attribute.text) {
+            return text;
+        }
+
+        if matching().any(|attribute| attribute.eol) {
+            return TextConversion::Text;
+        }
+
+        match self.autocrlf {
+            true => TextConversion::Auto,
+            false => TextConversion::None,
+        }
+    }
+}
+
+fn read_attributes(contents: &str, base: &Path, file: &Path, attributes: &mut Vec<Attribute>) {
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+
+        let pattern = match fields.next() {
+            Some(pattern) if !pattern.starts_with('#') => pattern,
+            _ => continue,
+        };
+
+        let mut text = None;
+        let mut eol = false;
+
+        for field in fields {
+            match field {
+                "text" => text = Some(TextConversion::Text),
+                "-text" | "binary" => text = Some(TextConversion::None),
+                "text=auto" => text = Some(TextConversion::Auto),
+                _ if field.starts_with("eol=") => eol = true,
+                _ => {}
+            }
+        }
+
+        if text.is_none() && !eol {
+            continue;
+        }
+
+        if let Ok(pattern) = FilePattern::build(pattern, base, false, &file.to_string_lossy()) {
+            attributes.push(Attribute { pattern, text, eol });
+        }
+    }
+}
+
+// Like git, text with a lone carriage return or a NUL byte is left alone.
+fn is_binary(contents: &[u8]) -> bool {
+    contents.iter().enumerate().any(|(index, byte)| {
+        *byte == 0 || (*byte == b'\r' && contents.get(index + 1) != Some(&b'\n'))
+    })
+}
+
+fn crlf_to_lf(contents: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(contents.len());
+
+    for (index, byte) in contents.iter().enumerate() {
+        if *byte != b'\r' || contents.get(index + 1) != Some(&b'\n') {
+            converted.push(*byte);
+        }
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{git, TempDirectory};
+    use std::process::Command;
+
+    fn repository(files: &[(&str, &str)], config: &[&str]) -> TempDirectory {
+        let directory = TempDirectory::new();
+        let root = directory.path();
+
+        git(root, &["init", "-q"]);
+        if !config.is_empty() {
+            git(root, &[&["config"], config].concat());
+        }
+
+        for (file, contents) in files {
+            fs::write(root.join(file), contents).unwrap();
+        }
+        git(root, &["add", "-A"]);
+        git(root, &["commit", "-q", "-m", "first"]);
+        directory
+    }
+
+    // Moves the modification time away from the one in the index, so the
+    // content has to be read.
+    fn touch(file: &Path, time: &str) {
+        let status = Command::new("touch")
+            .args(["-d", time])
+            .arg(file)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    fn status(root: &Path, file: &str) -> GitStatus {
+        GitFiles::read(root, Some("HEAD"))
+            .ok()
+            .unwrap()
+            .status(&root.join(file))
+    }
+
+    #[test]
+    fn autocrlf_files_match_their_normalized_blobs() {
+        let directory = repository(
+            &[("a.prg", "one\r\ntwo\r\n"), ("b.prg", "one\r\n")],
+            &["core.autocrlf", "true"],
+        );
+        let root = directory.path();
+        fs::write(root.join("b.prg"), "two\r\n").unwrap();
+        touch(&root.join("a.prg"), "@1000000000");
+
+        assert!(matches!(status(root, "a.prg"), GitStatus::Unchanged));
+        assert!(matches!(status(root, "b.prg"), GitStatus::Changed));
+    }
+
+    #[test]
+    fn text_attributes_match_their_normalized_blobs() {
+        let directory = repository(
+            &[
+                (".gitattributes", "*.prg text\n*.bin -text\n"),
+                ("a.prg", "one\r\ntwo\r\n"),
+                ("a.bin", "one\r\n"),
+            ],
+            &[],
+        );
+        let root = directory.path();
+        touch(&root.join("a.prg"), "@1000000000");
+        touch(&root.join("a.bin"), "@1000000000");
+
+        assert!(matches!(status(root, "a.prg"), GitStatus::Unchanged));
+        assert!(matches!(status(root, "a.bin"), GitStatus::Unchanged));
+    }
+
+    #[test]
+    fn racily_clean_files_are_read() {
+        let directory = repository(&[("a.prg", "aaaa\n")], &[]);
+        let root = directory.path();
+        let entry = find_repository(root)
+            .unwrap()
+            .index()
+            .ok()
+            .unwrap()
+            .remove(0);
+        let time = format!("@{}.{:09}", entry.modified, entry.modified_nanoseconds);
+
+        // Same size and modification time, written as the index was.
+        fs::write(root.join("a.prg"), "bbbb\n").unwrap();
+        touch(&root.join("a.prg"), &time);
+        touch(&root.join(".git").join("index"), &time);
+
+        assert!(matches!(status(root, "a.prg"), GitStatus::Changed));
+    }
+}
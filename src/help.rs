@@ -1,18 +1,85 @@
+use crate::arguments::{find_option, OptionSpec, OPTIONS};
+
 pub fn help() -> String {
-    String::from(
+    let mut help = String::from(
 "NAME
     exportbranch - export branch
 SYNOPSIS
-    exportbranch -s <source> -d <destination> [-c <only_copy_files>] [-f <file_filters>] [--md5] [--reload]
+    exportbranch [--config <file>] [--profile <name>] -s <source> -d <destination> [-f <pattern>]... [-e <pattern>]... [-c <pattern>]... [-x <directory>]... [-j <jobs>] [--conversion-table <file>] [--md5] [--md5-output] [--reload] [--lower] [--ignore-case] [--ignore-files] [--invalid-exb <policy>] [--tracked] [--since <revision>] [--mirror] [--watch] [--show] [-q | -v | -vv] [--log-file <file>] [--report-conversions] [--dry-run[=json]] [--output <format>]
+    exportbranch --dump-conversion-table [--conversion-table <file>]
+    exportbranch [--config <file>] [--profile <name>] -s <source> [-d <destination>] [options] --explain <file> [--diff <format>]
+    exportbranch explain <file> [--config <file>] [--profile <name>] -s <source> [-d <destination>] [options] [--diff <format>]
+    exportbranch verify [--config <file>] [--profile <name>] -s <source> -d <destination> [options]
+    exportbranch check [--config <file>] [--profile <name>] -s <source> [--ignore-files] [--invalid-exb <policy>]
+    exportbranch [--config <file>] --list-profiles
+    exportbranch --help [option]
+    exportbranch --version
 DESCRIPTION
     Export branch from source to destination
-    -s <source>             Source path
-    -d <destination>        Destination path
-    -c <only_copy_files>    Only copy files
-    -f <file_filters>       File filters
-    --md5                   Reload files
-    --reload                Reload files too
-    --lower                 Lowercase files
-    Example: exportbranch -s /home/user/branch -d /home/user/branch2 -c *.prg;*.mke;*.mkp;*.mks;*.mkc;*.hbp;*.hbc;*.hbm;*.ch;*.so*;*.cpp;*.a;*.c;*.h;*.sh;*.0;*.18;*.jar;*.spec -f *.a;*.so;*.h;*.0;*.18;*.jar;*.spec"
-)
+    Values may be given as --option value or --option=value
+COMMANDS
+    check                   Read every .exb and .exbignore file under the sources and report the
+                            unreadable files and invalid entries with their path and line
+    explain <file>          Explain how the file would be exported like --explain, then convert it in memory
+                            and print the substitutions made and a diff against the source
+    verify                  Compare every file the export would write with the destination, without
+                            writing anything, and report the missing, different and extra files
+OPTIONS
+",
+    );
+
+    for option in &OPTIONS {
+        help.push_str(&option_help(option));
+    }
+
+    help.push_str(
+"EXIT STATUS
+    0                       Success
+    1                       Some files could not be exported
+    2                       Invalid command line, exportbranch.toml or .exb entry
+    3                       A file or directory could not be read or written
+    4                       Invalid filter or exclusion pattern
+    5                       Invalid conversion table
+    6                       The git repository could not be read
+    7                       Watching the source failed
+    8                       verify found missing, different or extra files
+",
+    );
+
+    help.push_str(
+"    Example: exportbranch -s /home/user/branch -d /home/user/branch2 -f *.prg;*.mke;*.mkp;*.mks;*.mkc;*.hbp;*.hbc;*.hbm;*.ch;*.so*;*.cpp;*.a;*.c;*.h;*.sh;*.0;*.18;*.jar;*.spec -c *.a;*.so;*.h;*.0;*.18;*.jar;*.spec",
+    );
+
+    help
+}
+
+pub fn help_for(name: &str) -> Result<String, String> {
+    let option = ["", "-", "--"]
+        .iter()
+        .find_map(|prefix| find_option(&format!("{}{}", prefix, name)));
+
+    match option {
+        Some(option) => Ok(option_help(option).trim_end().to_string()),
+        None => Err(format!("Unknown option \"{}\"", name)),
+    }
+}
+
+pub fn version() -> String {
+    format!("exportbranch {}", env!("CARGO_PKG_VERSION"))
+}
+
+fn option_help(option: &OptionSpec) -> String {
+    let names = option.names();
+    let mut lines = option.help.lines();
+    let mut help = if names.len() < 24 {
+        format!("    {:<24}{}\n", names, lines.next().unwrap_or(""))
+    } else {
+        format!("    {}\n", names)
+    };
+
+    for line in lines {
+        help.push_str(&format!("    {:<24}{}\n", "", line));
+    }
+
+    help
 }
@@ -0,0 +1,203 @@
+use crate::error::Error;
+use crate::export_branch_files::read_file;
+use crate::file_pattern::Exclusion;
+use crate::git::find_repository;
+use std::path::Path;
+
+const GITIGNORE: &str = ".gitignore";
+const EXBIGNORE: &str = ".exbignore";
+
+// Patterns that apply before the walk reaches the source: the repository's
+// .git/info/exclude and the .gitignore files between its root and the source.
+pub fn repository_ignores(
+    source: &Path,
+    ignore_case: bool,
+    problems: &mut Vec<Error>,
+) -> Vec<Exclusion> {
+    let mut ignored = Vec::new();
+
+    let repository = match find_repository(source) {
+        Some(repository) => repository,
+        None => return ignored,
+    };
+
+    read_ignore_file(
+        &repository.git_directory.join("info").join("exclude"),
+        &repository.work_tree,
+        ignore_case,
+        &mut ignored,
+        problems,
+    );
+
+    let mut ancestors: Vec<&Path> = source
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor.starts_with(&repository.work_tree))
+        .collect();
+    ancestors.reverse();
+
+    for directory in ancestors {
+        read_ignore_file(
+            &directory.join(GITIGNORE),
+            directory,
+            ignore_case,
+            &mut ignored,
+            problems,
+        );
+    }
+
+    ignored
+}
+
+pub fn check_ignore_files(
+    directory: &Path,
+    ignored: &mut Vec<Exclusion>,
+    ignore_case: bool,
+    problems: &mut Vec<Error>,
+) {
+    for ignore_file in [GITIGNORE, EXBIGNORE] {
+        read_ignore_file(
+            &directory.join(ignore_file),
+            directory,
+            ignore_case,
+            ignored,
+            problems,
+        );
+    }
+}
+
+pub fn is_ignore_file(file: &Path) -> bool {
+    file.file_name()
+        .is_some_and(|file_name| file_name == GITIGNORE || file_name == EXBIGNORE)
+}
+
+// Unlike exclusions, an ignored directory is not walked at all, so as in git a
+// file inside it cannot be brought back by a `!pattern`.
+pub fn is_ignored(path: &Path, is_directory: bool, ignored: &[Exclusion]) -> bool {
+    if is_directory && path.file_name().is_some_and(|name| name == ".git") {
+        return true;
+    }
+
+    deciding_ignore(path, is_directory, ignored).is_some_and(|exclusion| !exclusion.is_negated())
+}
+
+pub fn deciding_ignore<'a>(
+    path: &Path,
+    is_directory: bool,
+    ignored: &'a [Exclusion],
+) -> Option<&'a Exclusion> {
+    ignored
+        .iter()
+        .rev()
+        .find(|exclusion| exclusion.is_match_entry(path, is_directory))
+}
+
+fn read_ignore_file(
+    file: &Path,
+    base: &Path,
+    ignore_case: bool,
+    ignored: &mut Vec<Exclusion>,
+    problems: &mut Vec<Error>,
+) {
+    let contents = match read_file(file, problems) {
+        Some(contents) => contents,
+        None => return,
+    };
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (pattern, negated) = match line.strip_prefix('!') {
+            Some(pattern) => (pattern, true),
+            None => (line.strip_prefix('\\').unwrap_or(line), false),
+        };
+
+        match Exclusion::build(
+            pattern,
+            negated,
+            base,
+            ignore_case,
+            &format!("{}:{}", file.to_string_lossy(), index + 1),
+        ) {
+            Ok(exclusion) => ignored.push(exclusion),
+            Err(err) => problems.push(Error::at(file, Some(index + 1), err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::TempDirectory;
+    use std::fs;
+
+    // A directory that find_repository takes for a work tree.
+    fn repository() -> TempDirectory {
+        let directory = TempDirectory::new();
+        fs::create_dir_all(directory.join(".git")).unwrap();
+        directory
+    }
+
+    #[test]
+    fn deeper_gitignores_override_the_repository_ones() {
+        let directory = repository();
+        let root = directory.path();
+        let source = root.join("sub").join("src");
+        directory.write(".git/info/exclude", "*.tmp\n");
+        directory.write(".gitignore", "*.log\n!keep.log\nbuild/\n");
+        directory.write("sub/.gitignore", "# comment\nkeep.log\n\\#name\n");
+        fs::create_dir_all(&source).unwrap();
+
+        let mut problems = Vec::new();
+        let ignored = repository_ignores(&source, false, &mut problems);
+
+        assert!(problems.is_empty());
+        assert!(is_ignored(&root.join("a.log"), false, &ignored));
+        assert!(!is_ignored(&root.join("keep.log"), false, &ignored));
+        assert!(is_ignored(&source.join("keep.log"), false, &ignored));
+        assert!(is_ignored(&source.join("x.tmp"), false, &ignored));
+        assert!(is_ignored(&source.join("#name"), false, &ignored));
+        assert!(is_ignored(&source.join("build"), true, &ignored));
+        assert!(!is_ignored(&source.join("build"), false, &ignored));
+        assert!(is_ignored(&source.join(".git"), true, &ignored));
+    }
+
+    #[test]
+    fn exbignore_is_read_after_gitignore() {
+        let directory = repository();
+        let root = directory.path();
+        directory.write("lib/.gitignore", "*.prg\n");
+        directory.write("lib/.exbignore", "!a.prg\n");
+
+        let mut problems = Vec::new();
+        let mut ignored = Vec::new();
+        check_ignore_files(&root.join("lib"), &mut ignored, false, &mut problems);
+
+        assert!(!is_ignored(&root.join("lib/a.prg"), false, &ignored));
+        assert!(is_ignored(&root.join("lib/b.prg"), false, &ignored));
+        assert_eq!(
+            deciding_ignore(&root.join("lib/a.prg"), false, &ignored)
+                .unwrap()
+                .text(),
+            "!a.prg"
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_problems() {
+        let directory = repository();
+        let root = directory.path();
+        directory.write(".gitignore", "ok.prg\n[abc\n");
+
+        let mut problems = Vec::new();
+        let mut ignored = Vec::new();
+        check_ignore_files(root, &mut ignored, false, &mut problems);
+
+        assert_eq!(ignored.len(), 1);
+        assert_eq!(problems.len(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::configuration::{Configuration, OutputFormat};
+use crate::error::{Error, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const PROGRESS_WIDTH: usize = 30;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Log {
+    file: Option<(PathBuf, File)>,
+    show_progress: bool,
+    progress: Option<Progress>,
+}
+
+struct Progress {
+    total: usize,
+    done: usize,
+    drawn: Option<Instant>,
+    width: usize,
+}
+
+impl Log {
+    pub fn open(configuration: &Configuration) -> Result<Log> {
+        let file = match configuration.log_file() {
+            Some(path) => Some((
+                path.to_path_buf(),
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| Error::io(path, err))?,
+            )),
+            None => None,
+        };
+
+        Ok(Log {
+            file,
+            show_progress: configuration.output() == OutputFormat::Text
+                && !configuration.dry_run()
+                && io::stderr().is_terminal(),
+            progress: None,
+        })
+    }
+
+    // Per-file lines go to the log file when there is one.
+    pub fn line(&mut self, line: &str) {
+        if self.file.is_some() {
+            self.write_file(line);
+            return;
+        }
+
+        self.clear_progress();
+        println!("{}", line);
+        self.draw_progress(true);
+    }
+
+    pub fn error(&mut self, line: &str) {
+        self.clear_progress();
+        eprintln!("{}", line);
+        self.write_file(line);
+        self.draw_progress(true);
+    }
+
+    // The summary is printed even when the per-file lines go to the log file.
+    pub fn summary(&mut self, summary: &str) {
+        self.write_file(summary.trim_end());
+        println!("{}", summary);
+    }
+
+    // The total grows as the walk finds the files to export.
+    pub fn start_progress(&mut self) {
+        if self.show_progress {
+            self.progress = Some(Progress {
+                total: 0,
+                done: 0,
+                drawn: None,
+                width: 0,
+            });
+        }
+    }
+
+    pub fn add_progress_total(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            progress.total += 1;
+        }
+
+        self.draw_progress(false);
+    }
+
+    pub fn advance_progress(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            progress.done += 1;
+        }
+
+        self.draw_progress(false);
+    }
+
+    pub fn finish_progress(&mut self) {
+        self.clear_progress();
+        self.progress = None;
+    }
+
+    fn write_file(&mut self, line: &str) {
+        if let Some((path, file)) = &mut self.file {
+            if let Err(err) = writeln!(file, "{}", line) {
+                eprintln!("{}", Error::io(path, err));
+                self.file = None;
+            }
+        }
+    }
+
+    fn draw_progress(&mut self, force: bool) {
+        let progress = match &mut self.progress {
+            Some(progress) if progress.total > 0 => progress,
+            _ => return,
+        };
+
+        if !force
+            && progress.done < progress.total
+            && progress
+                .drawn
+                .is_some_and(|drawn| drawn.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+
+        let filled = PROGRESS_WIDTH * progress.done / progress.total;
+        let bar = format!(
+            "[{}{}] {}/{} files",
+            "#".repeat(filled),
+            "-".repeat(PROGRESS_WIDTH - filled),
+            progress.done,
+            progress.total
+        );
+
+        eprint!("\r{}", bar);
+        progress.drawn = Some(Instant::now());
+        progress.width = bar.len();
+    }
+
+    fn clear_progress(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            if progress.width > 0 {
+                eprint!("\r{:width$}\r", "", width = progress.width);
+                progress.width = 0;
+            }
+        }
+    }
+}
@@ -1,89 +1,209 @@
+mod arguments;
+mod check;
 mod configuration;
 mod convert_file;
 mod convertions;
+mod diff;
+mod error;
 mod export;
 mod export_branch;
 mod export_branch_files;
+mod export_jobs;
+mod explain;
 mod file_checker;
+mod file_pattern;
+mod git;
+mod git_files;
 mod help;
+mod ignore_files;
+mod json;
+mod log;
+mod mirror;
+mod settings;
+mod summary;
+#[cfg(test)]
+mod test_fixtures;
+mod verify;
+mod watch;
 
-use configuration::Configuration;
+use check::check;
+use configuration::{Command, Configuration};
+use convertions::dump_conversion_table;
+use error::{Error, Result};
+use explain::explain;
 use export_branch::ExportBranch;
 use file_checker::FileChecker;
 use std::env;
 use std::path::Path;
 use std::path::PathBuf;
-use std::time::Instant;
+use verify::verify;
+use watch::{watch, WatchTarget};
 
 fn main() {
-    let timer = Instant::now();
-    let configuration = build_configuration();
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+fn run() -> Result<()> {
+    let configuration = Configuration::build(&mut env::args())?;
+
+    if let Some(message) = configuration.message() {
+        println!("{}", message);
+        return Ok(());
+    }
+
+    if configuration.dump_conversion_table() {
+        print!("{}", dump_conversion_table(configuration.convertions()));
+        return Ok(());
+    }
+
+    if configuration.list_profiles() {
+        print_profiles(&configuration);
+        return Ok(());
+    }
+
+    if configuration.command() == Command::Check {
+        return check(&configuration);
+    }
+
+    if configuration.command() == Command::Verify {
+        return verify_destinations(&configuration);
+    }
+
+    if let Some(file) = configuration.explain() {
+        return explain_file(file, &configuration);
+    }
 
     configuration.print();
 
+    let mut failed = 0;
+
     for source in configuration.source() {
         for destination in configuration.destination() {
-            export(source, destination, &configuration);
+            failed += export(source, destination, &configuration)?;
         }
     }
 
-    print_time_elapsed(timer);
+    // A failed initial export ends the run instead of starting to watch
+    // a destination that is already out of date.
+    if failed > 0 {
+        return Err(Error::Failed(failed));
+    }
+
+    if configuration.watch() {
+        watch_changes(&configuration)?;
+    }
+
+    Ok(())
 }
 
-fn build_configuration() -> Configuration {
-    Configuration::build(&mut env::args()).unwrap_or_else(|err: String| {
-        eprintln!("{}", err);
-        std::process::exit(1);
-    })
+fn print_profiles(configuration: &Configuration) {
+    for (name, inherits) in configuration.profiles() {
+        match inherits {
+            Some(inherits) => println!("{} (inherits {})", name, inherits),
+            None => println!("{}", name),
+        }
+    }
 }
 
-fn export(source: &String, destination: &String, configuration: &Configuration) {
-    let source_path_buffer = source_path(&source);
-    let destination_path_buffer = destination_path(&source, &destination);
-    let mut file_checker = FileChecker::new(Path::new(&destination).to_path_buf());
+fn export(source: &str, destination: &str, configuration: &Configuration) -> Result<usize> {
+    let source_path_buffer = source_path(source)?;
+    let destination_path_buffer = destination_path(source, destination)?;
+    let mut file_checker = FileChecker::new(Path::new(&destination).to_path_buf(), configuration);
     let mut export = ExportBranch::build(
         source_path_buffer,
         destination_path_buffer,
-        &configuration,
+        configuration,
         &mut file_checker,
+    )?;
+
+    export.perform_exporting()
+}
+
+fn explain_file(file: &str, configuration: &Configuration) -> Result<()> {
+    let file = source_path(file)?;
+    let mut found = None;
+
+    for source in configuration.source() {
+        if file.starts_with(source_path(source)?) {
+            found = Some(source);
+            break;
+        }
+    }
+
+    let source =
+        found.ok_or_else(|| Error::Config(format!("{:?} is not inside any source", file)))?;
+
+    let destinations = configuration
+        .destination()
+        .iter()
+        .map(|destination| destination_path(source, destination))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    let source = source_path(source)?;
+    let destination = destinations.first().unwrap_or(&source).to_path_buf();
+    let mut file_checker = FileChecker::new(destination.clone(), configuration);
+    let export = ExportBranch::build(source, destination, configuration, &mut file_checker)?;
+
+    print!(
+        "{}",
+        explain(&export, &file, &destinations, configuration.diff())?
     );
 
-    export.perform_exporting();
+    Ok(())
 }
 
-fn source_path(source: &String) -> PathBuf {
-    Path::new(&source).canonicalize().unwrap_or_else(|err| {
-        eprintln!("{}", err);
-        std::process::exit(1);
-    })
+fn verify_destinations(configuration: &Configuration) -> Result<()> {
+    let mut targets = Vec::new();
+
+    for source in configuration.source() {
+        for destination in configuration.destination() {
+            targets.push((source_path(source)?, destination_path(source, destination)?));
+        }
+    }
+
+    verify(configuration, &targets)
 }
 
-fn destination_path(source: &String, destination: &String) -> PathBuf {
-    
+fn watch_changes(configuration: &Configuration) -> Result<()> {
+    let mut targets = Vec::new();
+
+    for source in configuration.source() {
+        for destination in configuration.destination() {
+            targets.push(WatchTarget {
+                source: source_path(source)?,
+                destination: destination_path(source, destination)?,
+                file_checker: FileChecker::new(Path::new(&destination).to_path_buf(), configuration),
+            });
+        }
+    }
+
+    watch(configuration, targets)
+}
+
+fn source_path(source: &str) -> Result<PathBuf> {
+    Path::new(&source)
+        .canonicalize()
+        .map_err(|err| Error::io(Path::new(source), err))
+}
+
+fn destination_path(source: &str, destination: &str) -> Result<PathBuf> {
     if env::consts::OS == "windows" {
         let mut windows_destination = Path::new(destination).to_path_buf();
-        let windows_source_path = Path::new(source).ancestors().next().unwrap();
+        let windows_source_path = Path::new(source);
 
-        
-        match windows_source_path.components().next().unwrap() {
-            std::path::Component::Prefix(prefix) => {
-                windows_destination = Path::new(&windows_destination).join(&windows_source_path.strip_prefix(prefix.as_os_str()).unwrap());
+        match windows_source_path.components().next() {
+            Some(std::path::Component::Prefix(prefix)) => {
+                windows_destination = Path::new(&windows_destination).join(windows_source_path.strip_prefix(prefix.as_os_str()).unwrap_or(windows_source_path));
             }
             _ => {
-                eprintln!("Failed to get prefix from {:?}", windows_source_path);
-                std::process::exit(1);
+                return Err(Error::Config(format!("Failed to get prefix from {:?}", windows_source_path)));
             }
         }
 
-        return windows_destination;
+        return Ok(windows_destination);
     }
 
-    Path::new(&destination).to_path_buf()
-}
-
-fn print_time_elapsed(timer: Instant) {
-    println!(
-        "\r\n--------------------------\r\nTime elapsed: {:?} secs",
-        timer.elapsed().as_secs()
-    );
+    Ok(Path::new(&destination).to_path_buf())
 }
@@ -0,0 +1,207 @@
+use crate::configuration::{OutputFormat, Verbosity};
+use crate::error::Result;
+use crate::export::{format_lower, print_dry_run};
+use crate::export_branch::ExportBranch;
+use crate::json::json_string;
+use crate::log::Log;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn mirror(exportbranch: &mut ExportBranch) -> Result<()> {
+    let exported: HashSet<PathBuf> = exportbranch
+        .exported_files
+        .iter()
+        .chain(&exportbranch.unchanged_files)
+        .cloned()
+        .collect();
+
+    for (source_file, destination_file) in
+        exportbranch.file_checker.files_under(&exportbranch.source)
+    {
+        if exported.contains(&source_file) {
+            continue;
+        }
+
+        let reason = if source_file.exists() {
+            "no longer matched"
+        } else {
+            "source removed"
+        };
+
+        remove_orphan(exportbranch, &source_file, destination_file, reason)?;
+    }
+
+    Ok(())
+}
+
+pub fn prune_removed(exportbranch: &mut ExportBranch, removed: &Path) -> Result<()> {
+    for (source_file, destination_file) in exportbranch.file_checker.files_under(removed) {
+        if source_file.exists() {
+            continue;
+        }
+
+        if exportbranch.configuration.mirror() {
+            remove_orphan(
+                exportbranch,
+                &source_file,
+                destination_file,
+                "source removed",
+            )?;
+        } else if !exportbranch.configuration.dry_run() {
+            exportbranch.file_checker.remove_file(&source_file)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_orphan(
+    exportbranch: &mut ExportBranch,
+    source_file: &Path,
+    destination_file: Option<PathBuf>,
+    reason: &str,
+) -> Result<()> {
+    let configuration = exportbranch.configuration;
+
+    // Entries written before the destinations were recorded only have the
+    // source, the destination is where an export would put it today.
+    let destination_file = match destination_file {
+        Some(destination_file) => destination_file,
+        None => match derive_destination(exportbranch, source_file) {
+            Some(destination_file) => destination_file,
+            None => {
+                exportbranch.log.error(&format!(
+                    "Warning: keeping {:?}, its destination is unknown",
+                    source_file
+                ));
+                return Ok(());
+            }
+        },
+    };
+
+    if destination_file.is_file() {
+        if configuration.dry_run() {
+            print_dry_run(
+                &mut exportbranch.log,
+                configuration.output(),
+                "delete",
+                &destination_file,
+                None,
+                reason,
+            );
+            return Ok(());
+        }
+
+        if configuration.verbosity() > Verbosity::Quiet
+            || configuration.output() == OutputFormat::Json
+        {
+            print_removed(
+                &mut exportbranch.log,
+                configuration.output(),
+                &destination_file,
+                reason,
+            );
+        }
+
+        if let Err(err) = fs::remove_file(&destination_file) {
+            exportbranch
+                .log
+                .error(&format!("Error removing file: {}", err));
+            return Ok(());
+        }
+
+        remove_empty_directories(&destination_file, &exportbranch.destination);
+    }
+
+    if !configuration.dry_run() {
+        exportbranch.file_checker.remove_file(source_file)?;
+    }
+
+    Ok(())
+}
+
+fn derive_destination(exportbranch: &ExportBranch, source_file: &Path) -> Option<PathBuf> {
+    let relative = source_file.strip_prefix(&*exportbranch.source).ok()?;
+
+    format_lower(
+        exportbranch.destination.join(relative),
+        &exportbranch.destination,
+        exportbranch.configuration.lower(),
+    )
+    .ok()
+}
+
+fn remove_empty_directories(file: &Path, destination: &Path) {
+    let mut directory = file.parent();
+
+    while let Some(path) = directory {
+        if path == destination || !path.starts_with(destination) || fs::remove_dir(path).is_err() {
+            break;
+        }
+
+        directory = path.parent();
+    }
+}
+
+fn print_removed(log: &mut Log, output: OutputFormat, destination_file: &Path, reason: &str) {
+    match output {
+        OutputFormat::Text => log.line(&format!(
+            "{:<8} {} ({})",
+            "delete",
+            destination_file.to_string_lossy(),
+            reason
+        )),
+        OutputFormat::Json => log.line(&format!(
+            "{{\"event\":\"removed\",\"destination\":{},\"reason\":{}}}",
+            json_string(&destination_file.to_string_lossy()),
+            json_string(reason)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_checker::FileChecker;
+    use crate::test_fixtures::{configuration, TempDirectory};
+
+    #[test]
+    fn legacy_entries_remove_the_derived_destination() {
+        let directory = TempDirectory::new();
+        let source = directory.join("source");
+        let destination = directory.join("destination");
+        fs::create_dir_all(&source).unwrap();
+        directory.write("destination/sub/a.prg", "old");
+        directory.write("destination/kept.prg", "kept");
+        directory.write(
+            "destination/export_branch_files_metadata.txt",
+            format!("{}/Sub/A.prg;unknown\n", source.to_string_lossy()),
+        );
+
+        let configuration = configuration(&[
+            "-s",
+            &source.to_string_lossy(),
+            "-d",
+            &destination.to_string_lossy(),
+            "--mirror",
+            "--lower",
+            "-q",
+        ]);
+        let mut file_checker = FileChecker::new(destination.clone(), &configuration);
+        let mut exportbranch = ExportBranch::build(
+            source.clone(),
+            destination.clone(),
+            &configuration,
+            &mut file_checker,
+        )
+        .ok()
+        .unwrap();
+
+        mirror(&mut exportbranch).ok().unwrap();
+
+        assert!(!destination.join("sub").exists());
+        assert!(destination.join("kept.prg").exists());
+        assert!(exportbranch.file_checker.files_under(&source).is_empty());
+    }
+}
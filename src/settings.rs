@@ -0,0 +1,364 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const CONFIGURATION_FILE: &str = "exportbranch.toml";
+
+#[derive(Clone, Default)]
+pub struct Settings {
+    pub source: Option<Vec<String>>,
+    pub destination: Option<Vec<String>>,
+    pub only_copy_files: Option<Vec<String>>,
+    pub file_filters: Option<Vec<String>>,
+    pub exclusions: Option<Vec<String>>,
+    pub disregarded_directories: Option<Vec<String>>,
+    pub conversion_table: Option<String>,
+    pub jobs: Option<usize>,
+    pub md5: Option<bool>,
+    pub md5_output: Option<bool>,
+    pub reload: Option<bool>,
+    pub lower: Option<bool>,
+    pub ignore_case: Option<bool>,
+    pub ignore_files: Option<bool>,
+    pub tracked: Option<bool>,
+    pub since: Option<String>,
+    pub invalid_exb: Option<String>,
+    pub mirror: Option<bool>,
+    pub watch: Option<bool>,
+    pub show: Option<bool>,
+    pub dry_run: Option<bool>,
+    pub output: Option<String>,
+    pub verbosity: Option<String>,
+}
+
+pub struct SettingsFile {
+    path: PathBuf,
+    base: PathBuf,
+    table: Table,
+}
+
+impl Settings {
+    fn from_table(table: &Table, base: &Path, profile: bool) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+
+        for (key, value) in table {
+            match key.as_str() {
+                "profile" if !profile => {}
+                "inherits" if profile => {}
+                "source" => settings.source = Some(paths(key, value, base)?),
+                "destination" => settings.destination = Some(paths(key, value, base)?),
+                "only_copy_files" => settings.only_copy_files = Some(strings(key, value)?),
+                "file_filters" => settings.file_filters = Some(strings(key, value)?),
+                "exclude" => settings.exclusions = Some(strings(key, value)?),
+                "disregarded_directories" => {
+                    settings.disregarded_directories = Some(strings(key, value)?)
+                }
+                "conversion_table" => {
+                    settings.conversion_table = Some(path(base, &string(key, value)?))
+                }
+                "jobs" => match value.as_integer() {
+                    Some(jobs) if jobs > 0 => settings.jobs = Some(jobs as usize),
+                    _ => return Err(format!("\"{}\" must be a positive integer", key)),
+                },
+                "md5" => settings.md5 = Some(boolean(key, value)?),
+                "md5_output" => settings.md5_output = Some(boolean(key, value)?),
+                "reload" => settings.reload = Some(boolean(key, value)?),
+                "lower" => settings.lower = Some(boolean(key, value)?),
+                "ignore_case" => settings.ignore_case = Some(boolean(key, value)?),
+                "ignore_files" => settings.ignore_files = Some(boolean(key, value)?),
+                "tracked" => settings.tracked = Some(boolean(key, value)?),
+                "since" => settings.since = Some(string(key, value)?),
+                "invalid_exb" => settings.invalid_exb = Some(string(key, value)?),
+                "mirror" => settings.mirror = Some(boolean(key, value)?),
+                "watch" => settings.watch = Some(boolean(key, value)?),
+                "show" => settings.show = Some(boolean(key, value)?),
+                "dry_run" => settings.dry_run = Some(boolean(key, value)?),
+                "output" => settings.output = Some(string(key, value)?),
+                "verbosity" => settings.verbosity = Some(string(key, value)?),
+                _ => return Err(format!("unknown key \"{}\"", key)),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+impl SettingsFile {
+    pub fn discover(file: Option<&str>) -> Result<Option<SettingsFile>, String> {
+        let file = match file {
+            Some(file) => PathBuf::from(file),
+            None => {
+                let file = PathBuf::from(CONFIGURATION_FILE);
+
+                if !file.is_file() {
+                    return Ok(None);
+                }
+
+                file
+            }
+        };
+
+        SettingsFile::read(&file).map(Some)
+    }
+
+    pub fn read(file: &Path) -> Result<SettingsFile, String> {
+        let contents = fs::read_to_string(file)
+            .map_err(|err| format!("Failed to read {:?}: {}", file, err))?;
+        let table = contents
+            .parse::<Table>()
+            .map_err(|err| format!("Invalid {:?}: {}", file, err))?;
+
+        Ok(SettingsFile {
+            path: file.to_path_buf(),
+            base: file.parent().unwrap_or(Path::new("")).to_path_buf(),
+            table,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn settings(&self) -> Result<Settings, String> {
+        Settings::from_table(&self.table, &self.base, false)
+            .map_err(|err| format!("Invalid {:?}: {}", self.path, err))
+    }
+
+    // Returns the settings of the profile and of every profile it inherits from,
+    // starting with the most distant ancestor.
+    pub fn profile(&self, name: &str) -> Result<Vec<(String, Settings)>, String> {
+        let mut chain: Vec<(String, Settings)> = Vec::new();
+        let mut next = Some(name.to_string());
+
+        while let Some(name) = next {
+            if chain.iter().any(|(inherited, _)| *inherited == name) {
+                return Err(format!(
+                    "Invalid {:?}: profile \"{}\" is inherited in a cycle",
+                    self.path, name
+                ));
+            }
+
+            let table = self.profile_table(&name)?;
+            let settings = Settings::from_table(table, &self.base, true)
+                .map_err(|err| format!("Invalid {:?}: profile \"{}\": {}", self.path, name, err))?;
+
+            next = inherits(table)
+                .map_err(|err| format!("Invalid {:?}: profile \"{}\": {}", self.path, name, err))?;
+            chain.push((name, settings));
+        }
+
+        chain.reverse();
+        Ok(chain)
+    }
+
+    pub fn profiles(&self) -> Result<Vec<(String, Option<String>)>, String> {
+        let mut profiles = Vec::new();
+
+        for (name, value) in self.profiles_table()? {
+            match value.as_table() {
+                Some(table) => profiles.push((
+                    name.to_string(),
+                    inherits(table).map_err(|err| {
+                        format!("Invalid {:?}: profile \"{}\": {}", self.path, name, err)
+                    })?,
+                )),
+                None => {
+                    return Err(format!(
+                        "Invalid {:?}: profile \"{}\" must be a table",
+                        self.path, name
+                    ))
+                }
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    fn profiles_table(&self) -> Result<Table, String> {
+        match self.table.get("profile") {
+            Some(Value::Table(profiles)) => Ok(profiles.clone()),
+            Some(_) => Err(format!(
+                "Invalid {:?}: \"profile\" must be a table",
+                self.path
+            )),
+            None => Ok(Table::new()),
+        }
+    }
+
+    fn profile_table(&self, name: &str) -> Result<&Table, String> {
+        match self
+            .table
+            .get("profile")
+            .and_then(|profiles| profiles.get(name))
+        {
+            Some(Value::Table(table)) => Ok(table),
+            _ => Err(format!("Profile \"{}\" not found in {:?}", name, self.path)),
+        }
+    }
+}
+
+fn inherits(table: &Table) -> Result<Option<String>, String> {
+    match table.get("inherits") {
+        Some(value) => string("inherits", value).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn string(key: &str, value: &Value) -> Result<String, String> {
+    match value.as_str() {
+        Some(value) => Ok(value.to_string()),
+        None => Err(format!("\"{}\" must be a string", key)),
+    }
+}
+
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(value) => Ok(value
+            .split(';')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect()),
+        Value::Array(values) => values.iter().map(|value| string(key, value)).collect(),
+        _ => Err(format!(
+            "\"{}\" must be a string or an array of strings",
+            key
+        )),
+    }
+}
+
+fn paths(key: &str, value: &Value, base: &Path) -> Result<Vec<String>, String> {
+    Ok(strings(key, value)?
+        .iter()
+        .map(|value| path(base, value))
+        .collect())
+}
+
+fn path(base: &Path, value: &str) -> String {
+    base.join(value).to_string_lossy().to_string()
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, String> {
+    match value.as_bool() {
+        Some(value) => Ok(value),
+        None => Err(format!("\"{}\" must be true or false", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::TempDirectory;
+
+    fn settings(directory: &TempDirectory, contents: &str) -> Result<Settings, String> {
+        SettingsFile::read(&directory.write(CONFIGURATION_FILE, contents))?.settings()
+    }
+
+    #[test]
+    fn keys_are_read_with_paths_relative_to_the_file() {
+        let directory = TempDirectory::new();
+        let settings = settings(
+            &directory,
+            "source = \"src;lib\"\n\
+             destination = [\"/build\", \"out\"]\n\
+             file_filters = [\"*.prg\", \"*.ch\"]\n\
+             conversion_table = \"table.txt\"\n\
+             jobs = 2\n\
+             md5 = true\n\
+             verbosity = \"quiet\"\n",
+        )
+        .ok()
+        .unwrap();
+        let path = |name: &str| directory.join(name).to_string_lossy().to_string();
+
+        assert_eq!(settings.source, Some(vec![path("src"), path("lib")]));
+        assert_eq!(
+            settings.destination,
+            Some(vec!["/build".to_string(), path("out")])
+        );
+        assert_eq!(
+            settings.file_filters,
+            Some(vec!["*.prg".to_string(), "*.ch".to_string()])
+        );
+        assert_eq!(settings.conversion_table, Some(path("table.txt")));
+        assert_eq!(settings.jobs, Some(2));
+        assert_eq!(settings.md5, Some(true));
+        assert_eq!(settings.verbosity.as_deref(), Some("quiet"));
+        assert_eq!(settings.lower, None);
+    }
+
+    #[test]
+    fn invalid_values_name_the_file_and_the_key() {
+        let directory = TempDirectory::new();
+        let file = directory.join(CONFIGURATION_FILE);
+
+        for (contents, error) in [
+            ("colour = \"red\"", "unknown key \"colour\""),
+            ("jobs = 0", "\"jobs\" must be a positive integer"),
+            ("md5 = \"yes\"", "\"md5\" must be true or false"),
+            (
+                "source = 1",
+                "\"source\" must be a string or an array of strings",
+            ),
+        ] {
+            assert_eq!(
+                settings(&directory, contents).err(),
+                Some(format!("Invalid {:?}: {}", file, error))
+            );
+        }
+
+        assert!(settings(&directory, "source = [")
+            .err()
+            .unwrap()
+            .starts_with(&format!("Invalid {:?}: ", file)));
+    }
+
+    #[test]
+    fn profiles_come_after_the_ones_they_inherit() {
+        let directory = TempDirectory::new();
+        let file = SettingsFile::read(&directory.write(
+            CONFIGURATION_FILE,
+            "md5 = true\n\
+             [profile.linux]\n\
+             destination = \"linux\"\n\
+             [profile.release]\n\
+             inherits = \"linux\"\n\
+             lower = true\n\
+             [profile.first]\n\
+             inherits = \"second\"\n\
+             [profile.second]\n\
+             inherits = \"first\"\n",
+        ))
+        .ok()
+        .unwrap();
+
+        let chain = file.profile("release").ok().unwrap();
+        let names: Vec<&str> = chain.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["linux", "release"]);
+        assert!(chain[0].1.destination.is_some() && chain[0].1.lower.is_none());
+        assert_eq!(chain[1].1.lower, Some(true));
+
+        assert_eq!(
+            file.profile("first").err(),
+            Some(format!(
+                "Invalid {:?}: profile \"first\" is inherited in a cycle",
+                file.path()
+            ))
+        );
+        assert_eq!(
+            file.profile("windows").err(),
+            Some(format!(
+                "Profile \"windows\" not found in {:?}",
+                file.path()
+            ))
+        );
+        assert_eq!(
+            file.profiles().ok(),
+            Some(vec![
+                ("first".to_string(), Some("second".to_string())),
+                ("linux".to_string(), None),
+                ("release".to_string(), Some("linux".to_string())),
+                ("second".to_string(), Some("first".to_string())),
+            ])
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use crate::configuration::OutputFormat;
+use crate::convert_file::Transfer;
+use crate::json::json_string;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Summary {
+    pub scanned: usize,
+    pub matched: usize,
+    pub up_to_date: usize,
+    pub converted: usize,
+    pub copied: usize,
+    pub failed: Vec<(PathBuf, String)>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub substitutions: usize,
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    pub fn add_transfer(&mut self, only_copy: bool, transfer: &Transfer) {
+        if only_copy {
+            self.copied += 1;
+        } else {
+            self.converted += 1;
+        }
+
+        self.bytes_read += transfer.bytes_read;
+        self.bytes_written += transfer.bytes_written;
+        self.substitutions += transfer.substitutions;
+    }
+
+    pub fn format(&self, output: OutputFormat, source: &Path, destination: &Path) -> String {
+        match output {
+            OutputFormat::Text => self.format_text(source, destination),
+            OutputFormat::Json => self.format_json(source, destination),
+        }
+    }
+
+    fn format_text(&self, source: &Path, destination: &Path) -> String {
+        let mut text = format!(
+            "Summary: {} -> {}\n",
+            source.to_string_lossy(),
+            destination.to_string_lossy()
+        );
+
+        for (key, value) in [
+            ("scanned", self.scanned.to_string()),
+            ("matched", self.matched.to_string()),
+            ("up to date", self.up_to_date.to_string()),
+            ("converted", self.converted.to_string()),
+            ("copied", self.copied.to_string()),
+            ("failed", self.failed.len().to_string()),
+            ("bytes read", self.bytes_read.to_string()),
+            ("bytes written", self.bytes_written.to_string()),
+            ("substitutions", self.substitutions.to_string()),
+            ("time", format!("{:.3} secs", self.elapsed.as_secs_f64())),
+        ] {
+            text.push_str(&format!("    {:.<13}: {}\n", key, value));
+        }
+
+        if !self.failed.is_empty() {
+            text.push_str("Failed files:\n");
+
+            for (file, err) in &self.failed {
+                text.push_str(&format!("    {}: {}\n", file.to_string_lossy(), err));
+            }
+        }
+
+        text
+    }
+
+    fn format_json(&self, source: &Path, destination: &Path) -> String {
+        let failures: Vec<String> = self
+            .failed
+            .iter()
+            .map(|(file, err)| {
+                format!(
+                    "{{\"source\":{},\"error\":{}}}",
+                    json_string(&file.to_string_lossy()),
+                    json_string(err)
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"event\":\"summary\",\"source\":{},\"destination\":{},\"scanned\":{},\"matched\":{},\"up_to_date\":{},\"converted\":{},\"copied\":{},\"failed\":{},\"bytes_read\":{},\"bytes_written\":{},\"substitutions\":{},\"elapsed_ms\":{},\"failures\":[{}]}}",
+            json_string(&source.to_string_lossy()),
+            json_string(&destination.to_string_lossy()),
+            self.scanned,
+            self.matched,
+            self.up_to_date,
+            self.converted,
+            self.copied,
+            self.failed.len(),
+            self.bytes_read,
+            self.bytes_written,
+            self.substitutions,
+            self.elapsed.as_millis(),
+            failures.join(",")
+        )
+    }
+}
@@ -0,0 +1,104 @@
+use crate::configuration::Configuration;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+
+// An empty directory under the system temporary directory, unique to the test
+// that created it and removed with everything in it when dropped.
+pub struct TempDirectory {
+    path: PathBuf,
+}
+
+impl TempDirectory {
+    pub fn new() -> TempDirectory {
+        let path = std::env::temp_dir().join(format!(
+            "exportbranch-test-{}-{}",
+            process::id(),
+            DIRECTORIES.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        // Left behind by a process that had the same id and did not finish.
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDirectory { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+
+    // Writes the file, creating the directories it is in.
+    pub fn write(&self, file: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let file = self.path.join(file);
+
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, contents).unwrap();
+        file
+    }
+}
+
+impl Drop for TempDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn configuration(args: &[&str]) -> Configuration {
+    let args = ["exportbranch"]
+        .iter()
+        .chain(args)
+        .map(|arg| arg.to_string());
+
+    Configuration::build(&mut args.collect::<Vec<String>>().into_iter())
+        .ok()
+        .unwrap()
+}
+
+// Runs git without the user's or the system's configuration.
+pub fn git(directory: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(["-c", "commit.gpgsign=false", "-c", "gc.auto=0"])
+        .args([
+            "-c",
+            "core.safecrlf=false",
+            "-c",
+            "init.defaultBranch=master",
+        ])
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "git {:?} failed", args);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// A source whose configuration files have five problems: in exportbranch.exb a
+// bad regex on line 1, a `+=` and a `-=` on keys that only take `=` on lines 2
+// and 3, then a bad glob in sub/extecoesapenascopiar.exb and a directory where
+// sub/exportbranch.exb should be. The valid entries exclude *.bak and keep
+// *.prg in sub.
+pub fn malformed_source(directory: &TempDirectory) -> PathBuf {
+    directory.write(
+        "source/exportbranch.exb",
+        "filter += re:(\nconvert += false\nconversion-table -= table.txt\nexclude += *.bak\n",
+    );
+    directory.write("source/sub/extecoesapenascopiar.exb", "*.prg;[bad");
+    directory.write("source/a.prg", "a\r\n");
+    directory.write("source/b.bak", "b\r\n");
+    directory.write("source/sub/c.prg", "c\r\n");
+    fs::create_dir_all(directory.join("source/sub/exportbranch.exb")).unwrap();
+
+    directory.join("source")
+}
@@ -0,0 +1,388 @@
+use crate::configuration::{Configuration, OutputFormat, Verbosity};
+use crate::convert_file::Converter;
+use crate::error::{Error, Result};
+use crate::export::{export, source_path_display};
+use crate::export_branch::ExportBranch;
+use crate::export_jobs::{run_parallel, ExportJob};
+use crate::file_checker::FileChecker;
+use crate::git_files::GitFiles;
+use crate::json::json_string;
+use crate::log::Log;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+enum Verification {
+    Matches,
+    Missing,
+    // The line of the expected output where the destination first differs.
+    Differs(usize),
+    Failed(io::Error),
+}
+
+#[derive(Default)]
+struct Report {
+    checked: usize,
+    matching: usize,
+    missing: usize,
+    differing: usize,
+    extra: usize,
+    failed: usize,
+}
+
+// Walks each source the way the export does and compares every file it would
+// export with what is in the destination, without writing anything. Files in
+// the destination that no source would export are reported as extra.
+pub fn verify(configuration: &Configuration, targets: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let mut report = Report::default();
+    let mut expected: BTreeMap<PathBuf, HashSet<PathBuf>> = BTreeMap::new();
+
+    for (source, destination) in targets {
+        let mut file_checker = FileChecker::new(destination.clone(), configuration);
+        let mut exportbranch = ExportBranch::build(
+            source.clone(),
+            destination.clone(),
+            configuration,
+            &mut file_checker,
+        )?;
+        let filters = exportbranch.filters()?;
+
+        // --since only limits what an export rewrites, every tracked file is verified.
+        if configuration.tracked() {
+            exportbranch.git_files = Some(GitFiles::read(source, None).map_err(Error::Git)?);
+        }
+
+        export(
+            &mut exportbranch,
+            source.clone(),
+            destination.clone(),
+            &filters,
+        )?;
+
+        let jobs = std::mem::take(&mut exportbranch.jobs);
+        let converter = &exportbranch.converter;
+        let log = &mut exportbranch.log;
+        let files = expected.entry(destination.clone()).or_default();
+
+        run_parallel(
+            &jobs,
+            configuration.jobs(),
+            |job| compare(job, job.converter.as_deref().unwrap_or(converter)),
+            |job, verification| {
+                report.checked += 1;
+                files.insert(job.destination_file.clone());
+
+                let (status, detail) = match verification {
+                    Verification::Matches => {
+                        report.matching += 1;
+                        ("ok", None)
+                    }
+                    Verification::Missing => {
+                        report.missing += 1;
+                        ("missing", None)
+                    }
+                    Verification::Differs(line) => {
+                        report.differing += 1;
+                        (
+                            "differs",
+                            Some(format!("first difference at line {}", line)),
+                        )
+                    }
+                    Verification::Failed(err) => {
+                        report.failed += 1;
+                        ("failed", Some(err.to_string()))
+                    }
+                };
+
+                print_verification(
+                    log,
+                    configuration,
+                    status,
+                    Some(&job.source_file),
+                    &job.destination_file,
+                    detail.as_deref(),
+                );
+                Ok(())
+            },
+        )?;
+    }
+
+    let mut log = Log::open(configuration)?;
+
+    for (destination, files) in &expected {
+        let mut extra = Vec::new();
+        extra_files(destination, destination, files, &mut extra)?;
+
+        for file in extra {
+            report.extra += 1;
+            print_verification(&mut log, configuration, "extra", None, &file, None);
+        }
+    }
+
+    log.summary(&report.format(configuration.output()));
+
+    match report.mismatched() {
+        0 => Ok(()),
+        mismatched => Err(Error::Mismatch(mismatched)),
+    }
+}
+
+fn compare(job: &ExportJob, converter: &Converter) -> Verification {
+    let source = match fs::read(&job.source_file) {
+        Ok(source) => source,
+        Err(err) => return Verification::Failed(err),
+    };
+
+    let expected = match job.only_copy {
+        true => source,
+        false => converter.convert(&source).0,
+    };
+
+    let destination = match fs::read(&job.destination_file) {
+        Ok(destination) => destination,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Verification::Missing,
+        Err(err) => return Verification::Failed(err),
+    };
+
+    if expected == destination {
+        return Verification::Matches;
+    }
+
+    let offset = expected
+        .iter()
+        .zip(&destination)
+        .take_while(|(expected, destination)| expected == destination)
+        .count();
+
+    Verification::Differs(expected[..offset].iter().filter(|x| **x == b'\n').count() + 1)
+}
+
+fn extra_files(
+    directory: &Path,
+    destination: &Path,
+    expected: &HashSet<PathBuf>,
+    extra: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(directory)
+        .and_then(|entries| entries.collect::<io::Result<Vec<fs::DirEntry>>>())
+        .map_err(|err| Error::io(directory, err))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let metadata = directory == destination && FileChecker::is_metadata_file(&path);
+        let file_type = entry.file_type().map_err(|err| Error::io(&path, err))?;
+
+        // A symbolic link is never followed, a link to a parent would loop.
+        if file_type.is_dir() {
+            extra_files(&path, destination, expected, extra)?;
+        } else if !(metadata || expected.contains(&path)) {
+            extra.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// Matching files are listed with -v and in the JSON output, the others always
+// unless quiet.
+fn print_verification(
+    log: &mut Log,
+    configuration: &Configuration,
+    status: &str,
+    source: Option<&Path>,
+    destination: &Path,
+    detail: Option<&str>,
+) {
+    let source = source.map(|source| source_path_display(&source.to_string_lossy()).to_string());
+
+    match configuration.output() {
+        OutputFormat::Text => {
+            let shown = match configuration.verbosity() {
+                Verbosity::Quiet => false,
+                Verbosity::Normal => status != "ok",
+                _ => true,
+            };
+
+            if !shown {
+                return;
+            }
+
+            let mut line = match &source {
+                Some(source) => format!(
+                    "{:<8} {} -> {}",
+                    status,
+                    source,
+                    destination.to_string_lossy()
+                ),
+                None => format!("{:<8} {}", status, destination.to_string_lossy()),
+            };
+
+            if let Some(detail) = detail {
+                line.push_str(&format!(" ({})", detail));
+            }
+
+            log.line(&line);
+        }
+        OutputFormat::Json => log.line(&format!(
+            "{{\"event\":\"verify\",\"status\":{},\"source\":{},\"destination\":{},\"detail\":{}}}",
+            json_string(status),
+            source.map_or("null".to_string(), |source| json_string(&source)),
+            json_string(&destination.to_string_lossy()),
+            detail.map_or("null".to_string(), json_string)
+        )),
+    }
+}
+
+impl Report {
+    fn mismatched(&self) -> usize {
+        self.missing + self.differing + self.extra + self.failed
+    }
+
+    fn format(&self, output: OutputFormat) -> String {
+        match output {
+            OutputFormat::Text => {
+                let mut text = String::from("Verify:\n");
+
+                for (key, value) in [
+                    ("checked", self.checked),
+                    ("matching", self.matching),
+                    ("missing", self.missing),
+                    ("differing", self.differing),
+                    ("extra", self.extra),
+                    ("failed", self.failed),
+                ] {
+                    text.push_str(&format!("    {:.<13}: {}\n", key, value));
+                }
+
+                text
+            }
+            OutputFormat::Json => format!(
+                "{{\"event\":\"verify_summary\",\"checked\":{},\"matching\":{},\"missing\":{},\"differing\":{},\"extra\":{},\"failed\":{}}}",
+                self.checked,
+                self.matching,
+                self.missing,
+                self.differing,
+                self.extra,
+                self.failed
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{configuration, TempDirectory};
+
+    // The value of a field of a JSON line, without its quotes.
+    fn field<'a>(line: &'a str, name: &str) -> &'a str {
+        let start = line.find(&format!("\"{}\":", name)).unwrap() + name.len() + 3;
+
+        line[start..]
+            .split([',', '}'])
+            .next()
+            .unwrap()
+            .trim_matches('"')
+    }
+
+    // Verifies directory/source against directory/destination and returns the
+    // number of mismatches with the status, destination relative to
+    // directory/destination and detail of every file.
+    fn verify_destination(directory: &TempDirectory) -> (usize, Vec<(String, String, String)>) {
+        let source = directory.join("source");
+        let destination = directory.join("destination");
+        let log_file = directory.join("verify.log");
+        let configuration = configuration(&[
+            "-s",
+            &source.to_string_lossy(),
+            "-d",
+            &destination.to_string_lossy(),
+            "--output",
+            "json",
+            "--log-file",
+            &log_file.to_string_lossy(),
+        ]);
+
+        let mismatched = match verify(&configuration, &[(source, destination.clone())]) {
+            Ok(()) => 0,
+            Err(Error::Mismatch(mismatched)) => mismatched,
+            Err(err) => panic!("{}", err),
+        };
+        let verified = fs::read_to_string(&log_file)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("{\"event\":\"verify\","))
+            .map(|line| {
+                (
+                    field(line, "status").to_string(),
+                    field(line, "destination").replace(&*destination.to_string_lossy(), ""),
+                    field(line, "detail").to_string(),
+                )
+            })
+            .collect();
+
+        (mismatched, verified)
+    }
+
+    fn verified(status: &str, file: &str, detail: &str) -> (String, String, String) {
+        (status.to_string(), file.to_string(), detail.to_string())
+    }
+
+    #[test]
+    fn missing_differing_and_extra_files_are_reported() {
+        let directory = TempDirectory::new();
+        directory.write("source/a.prg", "a\r\nb\r\nc\r\n");
+        directory.write("source/b.prg", "b\r\n");
+        directory.write("source/c.prg", "c\r\n");
+        directory.write("source/sub/d.prg", "d\r\n");
+        directory.write("destination/a.prg", "a\nb\nX\n");
+        directory.write("destination/b.prg", "b\n");
+        directory.write("destination/sub/d.prg", "d\n");
+        directory.write("destination/extra.prg", "e\n");
+        // Only the metadata file of the destination itself is expected.
+        directory.write("destination/export_branch_files_metadata.txt", "");
+        directory.write("destination/sub/export_branch_files_metadata.txt", "");
+
+        assert_eq!(
+            verify_destination(&directory),
+            (
+                4,
+                vec![
+                    verified("differs", "/a.prg", "first difference at line 3"),
+                    verified("ok", "/b.prg", "null"),
+                    verified("missing", "/c.prg", "null"),
+                    verified("ok", "/sub/d.prg", "null"),
+                    verified("extra", "/extra.prg", "null"),
+                    verified("extra", "/sub/export_branch_files_metadata.txt", "null"),
+                ]
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_are_not_followed() {
+        let directory = TempDirectory::new();
+        directory.write("source/a.prg", "a\r\n");
+        let destination = directory.join("destination");
+        directory.write("destination/a.prg", "a\n");
+        std::os::unix::fs::symlink(&destination, destination.join("loop")).unwrap();
+
+        assert_eq!(
+            verify_destination(&directory),
+            (
+                1,
+                vec![
+                    verified("ok", "/a.prg", "null"),
+                    verified("extra", "/loop", "null"),
+                ]
+            )
+        );
+    }
+}
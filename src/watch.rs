@@ -0,0 +1,234 @@
+use crate::configuration::{Configuration, OutputFormat, Verbosity};
+use crate::error::{Error, Result};
+use crate::export::print_error;
+use crate::export_branch::ExportBranch;
+use crate::file_checker::FileChecker;
+use crate::log::Log;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+const DEBOUNCE: Duration = Duration::from_millis(300);
+const MAX_BATCH_AGE: Duration = Duration::from_secs(3);
+
+pub struct WatchTarget {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub file_checker: FileChecker,
+}
+
+pub fn watch(configuration: &Configuration, mut targets: Vec<WatchTarget>) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    let mut log = Log::open(configuration)?;
+    let mut sources: Vec<&Path> = targets
+        .iter()
+        .map(|target| target.source.as_path())
+        .collect();
+    sources.dedup();
+
+    for source in sources {
+        watcher
+            .watch(source, RecursiveMode::Recursive)
+            .map_err(watch_error)?;
+    }
+
+    let ignored: Vec<PathBuf> = targets
+        .iter()
+        .filter_map(|target| target.destination.canonicalize().ok())
+        .collect();
+
+    if configuration.output() == OutputFormat::Text && configuration.verbosity() > Verbosity::Quiet
+    {
+        println!("--------------------------\r\nWatching for changes...\r\n");
+    }
+
+    while let Some(changed) = next_changes(&receiver, &ignored, &Instant::now, &mut |err| {
+        print_error(&mut log, configuration.output(), &err.to_string())
+    }) {
+        for target in &mut targets {
+            match ExportBranch::build(
+                target.source.to_path_buf(),
+                target.destination.to_path_buf(),
+                configuration,
+                &mut target.file_checker,
+            ) {
+                Ok(mut export) => export.export_changes(&changed),
+                Err(err) => print_error(&mut log, configuration.output(), &err.to_string()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn watch_error(err: notify::Error) -> Error {
+    Error::Watch(err.to_string())
+}
+
+// Collects changes until none arrive for DEBOUNCE, or for at most MAX_BATCH_AGE
+// while they keep arriving. Accesses and changes to ignored paths do not
+// delay the batch. The time is read through `now` so the tests can move it.
+fn next_changes(
+    receiver: &Receiver<notify::Result<Event>>,
+    ignored: &[PathBuf],
+    now: &impl Fn() -> Instant,
+    report: &mut impl FnMut(notify::Error),
+) -> Option<Vec<PathBuf>> {
+    let mut changed = BTreeSet::new();
+    let mut started = now();
+    let mut last_change = started;
+
+    loop {
+        let event = if changed.is_empty() {
+            match receiver.recv() {
+                Ok(event) => event,
+                Err(_) => return None,
+            }
+        } else {
+            let deadline = (last_change + DEBOUNCE).min(started + MAX_BATCH_AGE);
+            let timeout = deadline.saturating_duration_since(now());
+
+            if timeout.is_zero() {
+                return Some(changed.into_iter().collect());
+            }
+
+            match receiver.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(_) => return Some(changed.into_iter().collect()),
+            }
+        };
+
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                report(err);
+                continue;
+            }
+        };
+
+        if let EventKind::Access(_) = event.kind {
+            continue;
+        }
+
+        let paths: Vec<PathBuf> = event
+            .paths
+            .into_iter()
+            .filter(|path| !ignored.iter().any(|ignored| path.starts_with(ignored)))
+            .collect();
+
+        if paths.is_empty() {
+            continue;
+        }
+
+        last_change = now();
+
+        if changed.is_empty() {
+            started = last_change;
+        }
+
+        changed.extend(paths);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind};
+    use std::cell::Cell;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn create(path: &str) -> notify::Result<Event> {
+        Ok(Event::new(EventKind::Create(CreateKind::File)).add_path(PathBuf::from(path)))
+    }
+
+    fn access(path: &str) -> notify::Result<Event> {
+        Ok(Event::new(EventKind::Access(AccessKind::Any)).add_path(PathBuf::from(path)))
+    }
+
+    // Batches of the queued events, with the clock moving a TICK every time
+    // it is read.
+    fn batches(events: Vec<notify::Result<Event>>, ignored: &[&str]) -> Vec<Vec<PathBuf>> {
+        let (sender, receiver) = mpsc::channel();
+        let ignored: Vec<PathBuf> = ignored.iter().map(PathBuf::from).collect();
+        let time = Cell::new(Instant::now());
+        let now = || time.replace(time.get() + TICK);
+        let mut batches = Vec::new();
+
+        for event in events {
+            sender.send(event).unwrap();
+        }
+        drop(sender);
+
+        while let Some(changed) = next_changes(&receiver, &ignored, &now, &mut |_| {}) {
+            batches.push(changed);
+        }
+
+        batches
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn accesses_and_ignored_paths_do_not_delay_the_batch() {
+        let mut events = vec![create("/source/a.prg")];
+
+        for _ in 0..20 {
+            events.push(access("/source/a.prg"));
+            events.push(create("/destination/a.prg"));
+        }
+        events.push(create("/source/b.prg"));
+
+        assert_eq!(
+            batches(events, &["/destination"]),
+            [paths(&["/source/a.prg"]), paths(&["/source/b.prg"])]
+        );
+    }
+
+    #[test]
+    fn a_busy_batch_ends_after_its_maximum_age() {
+        let names: Vec<String> = (0..100)
+            .map(|index| format!("/source/{:03}.prg", index))
+            .collect();
+        let events = names.iter().map(|name| create(name)).collect();
+
+        let batches = batches(events, &[]);
+
+        // The changes are a TICK or two apart, well within DEBOUNCE, so only
+        // the age of the batch splits them.
+        assert!(batches.len() > 1);
+        assert!(batches[..batches.len() - 1]
+            .iter()
+            .all(|batch| batch.len() == batches[0].len()));
+        assert_eq!(
+            batches.concat(),
+            names.iter().map(PathBuf::from).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn errors_are_reported_without_stopping_the_watch() {
+        let (sender, receiver) = mpsc::channel();
+        let mut errors = Vec::new();
+
+        sender.send(Err(notify::Error::generic("lost"))).unwrap();
+        sender.send(create("/source/a.prg")).unwrap();
+        drop(sender);
+
+        let mut report = |err: notify::Error| errors.push(err.to_string());
+        assert_eq!(
+            next_changes(&receiver, &[], &Instant::now, &mut report),
+            Some(paths(&["/source/a.prg"]))
+        );
+        assert_eq!(
+            next_changes(&receiver, &[], &Instant::now, &mut report),
+            None
+        );
+        assert_eq!(errors, ["lost"]);
+    }
+}